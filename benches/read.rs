@@ -0,0 +1,73 @@
+// cargo bench --bench read
+//
+// Reads the test fixtures scaled up to many thousand stations with the owned and the
+// borrowed reader.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tmlu_rs::tmlu::{read_cavefile, read_cavefile_ref, CaveFileReader};
+
+const COPIES: usize = 2000;
+
+fn scaled_fixture(name: &str) -> Vec<u8> {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("testdata")
+        .join(name);
+    let original = std::fs::read_to_string(path).unwrap();
+    let start = original.find("<Data>").unwrap() + "<Data>".len();
+    let end = original.find("</Data>").unwrap();
+    let stations = &original[start..end];
+    let mut scaled = String::with_capacity(original.len() + stations.len() * COPIES);
+    scaled.push_str(&original[..start]);
+    for _ in 0..COPIES {
+        scaled.push_str(stations);
+    }
+    scaled.push_str(&original[end..]);
+    scaled.into_bytes()
+}
+
+fn measure(name: &str, bytes: usize, mut f: impl FnMut() -> usize) {
+    let mut runs = 0;
+    let mut stations = 0;
+    let started = Instant::now();
+    while runs < 3 || started.elapsed() < Duration::from_secs(2) {
+        stations = black_box(f());
+        runs += 1;
+    }
+    let per_run = started.elapsed() / runs;
+    let mb_per_s = bytes as f64 / per_run.as_secs_f64() / 1_000_000.0;
+    println!(
+        "{:<12} {:>8} stations {:>10.2?}/run {:>8.1} MB/s",
+        name, stations, per_run, mb_per_s
+    );
+}
+
+fn main() {
+    for fixture in ["circle_closed.tmlu", "bowtie_closed.tmlu", "test1.tmlu"] {
+        let input = scaled_fixture(fixture);
+        println!("{} x{} ({} bytes)", fixture, COPIES, input.len());
+        measure("owned", input.len(), || {
+            read_cavefile(input.as_slice()).data.len()
+        });
+        measure("streaming", input.len(), || {
+            CaveFileReader::new(input.as_slice())
+                .unwrap()
+                .map(|srvd| black_box(srvd.unwrap()))
+                .fold(0, |count, _| count + 1)
+        });
+        measure("borrowed", input.len(), || {
+            read_cavefile_ref(&input).unwrap().data.len()
+        });
+        measure("borrowed-it", input.len(), || {
+            let mut reader = CaveFileReader::new(input.as_slice()).unwrap();
+            let mut count = 0;
+            while let Some(srvd) = reader.next_ref() {
+                black_box(srvd.unwrap());
+                count += 1;
+            }
+            count
+        });
+    }
+}
@@ -0,0 +1,191 @@
+// cargo bench --bench write
+//
+// Writes the test fixtures scaled up to many thousand stations, with the crate's writer and
+// with the xml-rs writer it replaced as a baseline.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tmlu_rs::tmlu::{read_cavefile, write_cavefile, CaveFileInfo, SurveyData};
+
+const COPIES: usize = 2000;
+
+fn measure(name: &str, data: &[SurveyData], mut f: impl FnMut(Vec<SurveyData>, &mut Vec<u8>)) {
+    let mut output = Vec::new();
+    let mut runs = 0;
+    let mut elapsed = Duration::ZERO;
+    while runs < 3 || elapsed < Duration::from_secs(2) {
+        let data = data.to_vec();
+        output.clear();
+        let started = Instant::now();
+        f(data, &mut output);
+        elapsed += started.elapsed();
+        black_box(&output);
+        runs += 1;
+    }
+    let per_run = elapsed / runs;
+    let mb_per_s = output.len() as f64 / per_run.as_secs_f64() / 1_000_000.0;
+    println!(
+        "{:<38} {} stations {} bytes {:>10.2?}/run {:>8.1} MB/s",
+        name,
+        data.len(),
+        output.len(),
+        per_run,
+        mb_per_s
+    );
+}
+
+fn main() {
+    for fixture in ["circle_closed.tmlu", "bowtie_closed.tmlu", "test1.tmlu"] {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("testdata")
+            .join(fixture);
+        let cave = read_cavefile(std::io::BufReader::new(std::fs::File::open(path).unwrap()));
+        let mut data = Vec::with_capacity(cave.data.len() * COPIES);
+        for _ in 0..COPIES {
+            data.extend(cave.data.iter().cloned());
+        }
+
+        let name = format!("{} x{}", fixture, COPIES);
+        measure(&format!("{} emitter", name), &data, |data, output| {
+            write_cavefile(output, data, cave.info.clone()).unwrap()
+        });
+        measure(&format!("{} xml-rs", name), &data, |data, output| {
+            baseline::write_cavefile(output, data, &cave.info).unwrap()
+        });
+    }
+}
+
+// The xml-rs writer from before the emitter, without the unknown elements, which the
+// fixtures do not have
+mod baseline {
+    use super::{CaveFileInfo, SurveyData};
+    use xml::common::XmlVersion;
+    use xml::writer::{EmitterConfig, Error, EventWriter, XmlEvent};
+
+    fn element<W: std::io::Write>(
+        writer: &mut EventWriter<W>,
+        name: &str,
+        val: &str,
+    ) -> Result<(), Error> {
+        writer.write(XmlEvent::start_element(name))?;
+        let escaped = xml::escape::escape_str_pcdata(val);
+        if val.contains('>') {
+            writer.write(XmlEvent::characters(&escaped.replace('>', "&gt;")))?;
+        } else {
+            writer.write(XmlEvent::characters(&escaped))?;
+        }
+        writer.write(XmlEvent::end_element())
+    }
+
+    fn element_fast<W: std::io::Write>(
+        writer: &mut EventWriter<W>,
+        name: &str,
+        val: &str,
+    ) -> Result<(), Error> {
+        writer.write(XmlEvent::start_element(name))?;
+        writer.write(XmlEvent::characters(val))?;
+        writer.write(XmlEvent::end_element())
+    }
+
+    fn empty<W: std::io::Write>(writer: &mut EventWriter<W>, name: &str) -> Result<(), Error> {
+        writer.write(XmlEvent::start_element(name))?;
+        writer.write(XmlEvent::end_element())
+    }
+
+    pub fn write_cavefile<W: std::io::Write>(
+        output: W,
+        survey_data: Vec<SurveyData>,
+        info: &CaveFileInfo,
+    ) -> Result<(), Error> {
+        let mut config = EmitterConfig::new()
+            .perform_indent(true)
+            .indent_string("")
+            .pad_self_closing(false);
+        config.perform_escaping = false;
+        let mut w = config.create_writer(output);
+
+        w.write(XmlEvent::StartDocument {
+            version: XmlVersion::Version10,
+            encoding: Some("UTF-8"),
+            standalone: Some(true),
+        })?;
+        w.write(XmlEvent::start_element("CaveFile"))?;
+        element(&mut w, "caveName", &info.cave_name)?;
+        element(
+            &mut w,
+            "firstStartAbsoluteElevation",
+            &info.first_start_absolute_elevation,
+        )?;
+        element(
+            &mut w,
+            "geoCoding",
+            info.geo_coding.as_deref().unwrap_or_default(),
+        )?;
+        empty(&mut w, "ListAnnotation")?;
+        w.write(XmlEvent::start_element("Data"))?;
+        for srvd in survey_data {
+            w.write(XmlEvent::start_element("SRVD"))?;
+            element_fast(&mut w, "AZ", &srvd.azimuth)?;
+            element_fast(&mut w, "CID", &srvd.closure_to_id.to_string())?;
+            element_fast(&mut w, "CL", &srvd.color)?;
+            element(&mut w, "CM", srvd.comment.as_deref().unwrap_or_default())?;
+            element_fast(&mut w, "DT", &srvd.date)?;
+            element_fast(&mut w, "DP", &srvd.depth)?;
+            element_fast(&mut w, "DPI", &srvd.depth_in)?;
+            element(&mut w, "D", &srvd.down)?;
+            element(&mut w, "EXC", &srvd.excluded)?;
+            element(&mut w, "EX", srvd.explorer.as_deref().unwrap_or_default())?;
+            element_fast(&mut w, "FRID", &srvd.from_id.to_string())?;
+            element_fast(&mut w, "ID", &srvd.id.to_string())?;
+            element_fast(&mut w, "INC", &srvd.inclination)?;
+            element_fast(&mut w, "LT", &srvd.latitude)?;
+            element_fast(&mut w, "L", &srvd.left)?;
+            element_fast(&mut w, "LG", &srvd.length)?;
+            element_fast(&mut w, "LK", &srvd.locked)?;
+            element_fast(&mut w, "LGT", &srvd.longitude)?;
+            element(&mut w, "NM", srvd.name.as_deref().unwrap_or_default())?;
+            element_fast(&mut w, "PRTY", &srvd.profile_type)?;
+            element_fast(&mut w, "R", &srvd.right)?;
+            element(&mut w, "SC", srvd.section.as_deref().unwrap_or_default())?;
+            w.write(XmlEvent::start_element("SH"))?;
+            element_fast(&mut w, "HPRA", &srvd.shape.has_profile_azimut)?;
+            element_fast(&mut w, "HPRT", &srvd.shape.has_profile_tilt)?;
+            element_fast(&mut w, "PRAZ", &srvd.shape.profile_azimut)?;
+            element_fast(&mut w, "PRT", &srvd.shape.profile_tilt)?;
+            w.write(XmlEvent::start_element("RC"))?;
+            for rv in &srvd.shape.radius_collection {
+                w.write(XmlEvent::start_element("RV"))?;
+                element_fast(&mut w, "ag", &rv.angle)?;
+                element_fast(&mut w, "lg", &rv.length)?;
+                element_fast(&mut w, "tc", &rv.tension_corridor)?;
+                element_fast(&mut w, "tp", &rv.tension_profile)?;
+                w.write(XmlEvent::end_element())?;
+            }
+            w.write(XmlEvent::end_element())?;
+            w.write(XmlEvent::end_element())?;
+            element_fast(&mut w, "TY", &srvd.station_type)?;
+            element_fast(&mut w, "U", &srvd.up)?;
+            w.write(XmlEvent::end_element())?;
+        }
+        w.write(XmlEvent::end_element())?;
+        element(&mut w, "unit", &info.unit)?;
+        element(&mut w, "useMagneticAzimuth", &info.use_magnetic_azimuth)?;
+        for name in [
+            "Constraints",
+            "CartoLine",
+            "CartoPage",
+            "CartoRectangle",
+            "CartoSelection",
+            "CartoEllipse",
+            "CartoSpline",
+            "Layers",
+            "CartoOverlay",
+            "CartoLinkedSurface",
+        ] {
+            empty(&mut w, name)?;
+        }
+        w.write(XmlEvent::end_element())
+    }
+}
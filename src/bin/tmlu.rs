@@ -0,0 +1,604 @@
+// cargo install --path . --features cli
+//
+// Exits with 0 on success, 1 when validate finds problems, diff finds changes or a three-way
+// merge has conflicts, and 2 on errors. `-` reads stdin or writes stdout.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use tmlu_rs::csv::{read_csv, write_csv, CsvFormat, Stations};
+use tmlu_rs::diff::{diff, render_text};
+use tmlu_rs::geomag::Model;
+use tmlu_rs::merge::{merge, merge3};
+use tmlu_rs::people::people;
+use tmlu_rs::renumber::{renumber, Order};
+use tmlu_rs::split::{split_section, split_subtree};
+use tmlu_rs::sqlite;
+use tmlu_rs::stats::{stats, Tally};
+use tmlu_rs::tml::{self, read_tml, write_tml};
+use tmlu_rs::tmlu::{try_read_cavefile, write_cavefile, CaveFile};
+use tmlu_rs::traverse::traverse;
+use tmlu_rs::units::{convert_units, Unit};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(version, about = "Reads, checks and edits Ariane cave surveys")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// A `.COF` file with a release of the World Magnetic Model to use next to the embedded
+    /// WMM2020, such as WMM2025
+    #[arg(long, global = true)]
+    cof: Vec<String>,
+    /// The IGRF coefficient table (`igrf14coeffs.txt`), for dates the World Magnetic Model
+    /// releases do not cover
+    #[arg(long, global = true)]
+    igrf: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Tmlu,
+    Tml,
+    Json,
+    Sqlite,
+    Csv,
+}
+
+impl Format {
+    fn from_extension(path: &str) -> Option<Format> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "tmlu" | "xml" => Some(Format::Tmlu),
+            "tml" => Some(Format::Tml),
+            "json" => Some(Format::Json),
+            "sqlite" | "sqlite3" | "db" => Some(Format::Sqlite),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    // Tells JSON and SQLite apart, the library tells tmlu from tml
+    fn detect(bytes: &[u8]) -> Format {
+        if bytes.starts_with(b"SQLite format 3\0") {
+            Format::Sqlite
+        } else if bytes.trim_ascii_start().starts_with(b"{") {
+            Format::Json
+        } else {
+            match tml::Format::detect(bytes) {
+                tml::Format::Tmlu => Format::Tmlu,
+                tml::Format::Tml => Format::Tml,
+            }
+        }
+    }
+}
+
+#[derive(Args)]
+struct Input {
+    /// The survey to read, `-` for stdin
+    #[arg(default_value = "-")]
+    input: String,
+    /// Format of the input, from the extension or the contents if not given
+    #[arg(long)]
+    from: Option<Format>,
+}
+
+#[derive(Args)]
+struct Output {
+    /// Where to write the survey, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    output: String,
+    /// Format of the output, from the extension of the output if not given, tmlu on stdout
+    #[arg(long)]
+    to: Option<Format>,
+}
+
+#[derive(Args)]
+struct Csv {
+    /// The survey the CSV shots start from, when reading CSV
+    #[arg(long)]
+    base: Option<String>,
+    /// Semicolons between cells and decimal commas
+    #[arg(long)]
+    decimal_comma: bool,
+    /// Stations are ids instead of names
+    #[arg(long)]
+    ids: bool,
+    /// Unit of the CSV lengths, the unit of the survey if not given
+    #[arg(long, value_parser = parse_unit)]
+    csv_unit: Option<Unit>,
+}
+
+impl Csv {
+    fn format(&self) -> CsvFormat {
+        let format = if self.decimal_comma {
+            CsvFormat::decimal_comma()
+        } else {
+            CsvFormat::default()
+        };
+        CsvFormat {
+            stations: if self.ids {
+                Stations::Ids
+            } else {
+                Stations::Names
+            },
+            unit: self.csv_unit,
+            ..format
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RenumberOrder {
+    File,
+    Traversal,
+    Sorted,
+}
+
+fn parse_unit(unit: &str) -> std::result::Result<Unit, String> {
+    unit.parse()
+        .map_err(|e: tmlu_rs::error::Error| e.to_string())
+}
+
+fn parse_tie(tie: &str) -> std::result::Result<(i32, i32), String> {
+    let (a, b) = tie.split_once(':').ok_or("expected <station>:<station>")?;
+    match (a.trim().parse(), b.trim().parse()) {
+        (Ok(a), Ok(b)) => Ok((a, b)),
+        _ => Err("expected two station ids".to_string()),
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lengths, depths, extents and counts of a survey
+    #[command(alias = "stats")]
+    Info {
+        #[command(flatten)]
+        input: Input,
+        /// Totals per explorer and surveyor instead
+        #[arg(long)]
+        people: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Checks that the stations refer to each other and their numbers can be read
+    Validate {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Converts between tmlu, tml, JSON, SQLite and CSV
+    Convert {
+        #[command(flatten)]
+        input: Input,
+        #[command(flatten)]
+        output: Output,
+        #[command(flatten)]
+        csv: Csv,
+    },
+    /// Lists the differences between two versions of a survey
+    Diff {
+        old: String,
+        new: String,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Adds the stations of one survey to another, or merges two edits of a survey
+    Merge {
+        ours: String,
+        theirs: String,
+        /// The common ancestor, for a three-way merge
+        #[arg(long)]
+        base: Option<String>,
+        /// Joins station <ours>:<theirs> of the two surveys with a CLOSURE row
+        #[arg(long, value_parser = parse_tie, conflicts_with = "base")]
+        tie: Option<(i32, i32)>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Extracts a section or a station and everything surveyed from it
+    Split {
+        #[command(flatten)]
+        input: Input,
+        #[arg(long, required_unless_present = "subtree", conflicts_with = "subtree")]
+        section: Option<String>,
+        #[arg(long)]
+        subtree: Option<i32>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Gives the stations new ids from 0
+    Renumber {
+        #[command(flatten)]
+        input: Input,
+        #[arg(long, value_enum, default_value = "file")]
+        order: RenumberOrder,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Converts the lengths to meters or feet
+    Units {
+        #[command(flatten)]
+        input: Input,
+        /// m or ft
+        #[arg(long, value_parser = parse_unit)]
+        unit: Unit,
+        #[command(flatten)]
+        output: Output,
+    },
+}
+
+fn read_bytes(path: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if path == "-" {
+        io::stdin().lock().read_to_end(&mut bytes)?;
+    } else {
+        File::open(path)?.read_to_end(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+fn read(path: &str, format: Option<Format>) -> Result<CaveFile> {
+    let format = format.or_else(|| Format::from_extension(path));
+    if format == Some(Format::Sqlite) {
+        if path == "-" {
+            return Err("SQLite databases can not be read from stdin".into());
+        }
+        return Ok(sqlite::load(path)?);
+    }
+    let bytes = read_bytes(path)?;
+    match format.unwrap_or_else(|| Format::detect(&bytes)) {
+        Format::Tmlu => Ok(try_read_cavefile(&bytes[..])?),
+        Format::Tml => Ok(read_tml(Cursor::new(bytes))?),
+        Format::Json => Ok(serde_json::from_slice(&bytes)?),
+        Format::Sqlite => read(path, Some(Format::Sqlite)),
+        Format::Csv => Err("CSV can only be read by convert, with --base".into()),
+    }
+}
+
+fn write(cave: CaveFile, output: &Output, csv: &CsvFormat, model: &Model) -> Result<()> {
+    let path = output.output.as_str();
+    let format = output
+        .to
+        .or_else(|| Format::from_extension(path))
+        .unwrap_or(Format::Tmlu);
+    if format == Format::Sqlite {
+        if path == "-" {
+            return Err("SQLite databases can not be written to stdout".into());
+        }
+        if Path::new(path).exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(sqlite::save(path, &cave, model)?);
+    }
+
+    let mut out: Box<dyn Write> = if path == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    };
+    match format {
+        Format::Tmlu => write_cavefile(&mut out, cave.data, cave.info)?,
+        Format::Tml => {
+            // The zip writer needs to seek
+            let mut zip = Cursor::new(Vec::new());
+            write_tml(&mut zip, cave.data, cave.info)?;
+            out.write_all(zip.get_ref())?;
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &cave)?;
+            writeln!(out)?;
+        }
+        Format::Csv => write_csv(&mut out, &cave, csv)?,
+        Format::Sqlite => unreachable!(),
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn info(out: &mut impl Write, cave: &CaveFile, json: bool, model: &Model) -> Result<()> {
+    let stats = stats(cave, model)?;
+    if json {
+        writeln!(out, "{}", serde_json::to_string(&stats)?)?;
+        return Ok(());
+    }
+    let unit = &stats.unit;
+    let tally = |t: Tally| format!("{} legs, {:.2} {}", t.legs, t.length, unit);
+    writeln!(out, "name: {}", cave.info.cave_name)?;
+    writeln!(out, "unit: {}", unit)?;
+    writeln!(out, "stations: {}", stats.stations)?;
+    writeln!(out, "loops: {}", stats.loops)?;
+    writeln!(out, "surveyed: {}", tally(stats.surveyed))?;
+    writeln!(out, "excluded: {}", tally(stats.excluded))?;
+    writeln!(out, "virtual: {}", tally(stats.virtual_legs))?;
+    writeln!(out, "max depth: {:.2} {}", stats.max_depth, unit)?;
+    writeln!(out, "vertical range: {:.2} {}", stats.vertical_range, unit)?;
+    writeln!(
+        out,
+        "extent: {:.2} {} east-west, {:.2} {} north-south, {:.2} {} across",
+        stats.east_west, unit, stats.north_south, unit, stats.horizontal_extent, unit
+    )?;
+    for (title, breakdown) in [
+        ("section", &stats.by_section),
+        ("year", &stats.by_year),
+        ("explorer", &stats.by_explorer),
+    ] {
+        for (key, t) in breakdown {
+            writeln!(out, "{} {:?}: {}", title, key, tally(*t))?;
+        }
+    }
+    Ok(())
+}
+
+fn people_info(out: &mut impl Write, cave: &CaveFile, json: bool) -> Result<()> {
+    let people = people(cave)?;
+    if json {
+        writeln!(out, "{}", serde_json::to_string(&people)?)?;
+        return Ok(());
+    }
+    for person in &people {
+        writeln!(
+            out,
+            "{}: explored {} legs, {:.2} m, surveyed {} legs, {} to {}",
+            person.name,
+            person.legs_explored,
+            person.meters_explored,
+            person.legs_surveyed,
+            person.first_date.as_deref().unwrap_or("?"),
+            person.last_date.as_deref().unwrap_or("?"),
+        )?;
+    }
+    Ok(())
+}
+
+// Everything wrong with the stations, one line each
+fn problems(cave: &CaveFile) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Err(e) = cave.info.unit.parse::<Unit>() {
+        problems.push(e.to_string());
+    }
+    let mut ids = HashSet::new();
+    for srvd in &cave.data {
+        if !ids.insert(srvd.id) {
+            problems.push(format!(
+                "station {}: the id is used more than once",
+                srvd.id
+            ));
+        }
+    }
+    let stations: HashMap<i32, &str> = cave
+        .data
+        .iter()
+        .filter(|s| s.station_type != "CLOSURE")
+        .map(|s| (s.id, s.station_type.as_str()))
+        .collect();
+    if !stations.values().any(|ty| *ty == "START") {
+        problems.push("there is no START station".to_string());
+    }
+
+    let mut numbers_ok = true;
+    for srvd in &cave.data {
+        let id = srvd.id;
+        let refers = match srvd.station_type.as_str() {
+            "START" => vec![],
+            "REAL" | "VIRTUAL" => vec![srvd.from_id],
+            "CLOSURE" => vec![srvd.from_id, srvd.closure_to_id],
+            other => {
+                problems.push(format!("station {}: unknown type {:?}", id, other));
+                continue;
+            }
+        };
+        for to in refers {
+            if !stations.contains_key(&to) {
+                problems.push(format!("station {}: refers to missing station {}", id, to));
+            }
+        }
+        if srvd.station_type == "CLOSURE" {
+            continue;
+        }
+        let fields = [
+            ("LG", &srvd.length),
+            ("AZ", &srvd.azimuth),
+            ("INC", &srvd.inclination),
+            ("DP", &srvd.depth),
+            ("L", &srvd.left),
+            ("R", &srvd.right),
+            ("U", &srvd.up),
+            ("D", &srvd.down),
+            ("LT", &srvd.latitude),
+            ("LGT", &srvd.longitude),
+        ];
+        for (tag, value) in fields {
+            match value.trim().parse::<f64>() {
+                Ok(v) if v.is_finite() => {
+                    if (tag == "LG" && v < 0.0) || (tag == "AZ" && !(0.0..=360.0).contains(&v)) {
+                        problems.push(format!("station {}: <{}> is out of range: {}", id, tag, v));
+                    }
+                }
+                _ => {
+                    numbers_ok = false;
+                    problems.push(format!(
+                        "station {}: <{}> is not a number: {:?}",
+                        id, tag, value
+                    ));
+                }
+            }
+        }
+    }
+
+    if numbers_ok {
+        match traverse(cave) {
+            Ok(traverse) => {
+                for srvd in cave.data.iter().filter(|s| s.station_type != "CLOSURE") {
+                    if traverse.get(srvd.id).is_none() {
+                        problems.push(format!(
+                            "station {}: can not be reached from a START station",
+                            srvd.id
+                        ));
+                    }
+                }
+            }
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+    problems
+}
+
+fn run(cli: Cli) -> Result<ExitCode> {
+    // The World Magnetic Model takes over from the IGRF where both have a release
+    let mut model = match &cli.igrf {
+        Some(path) => {
+            let mut igrf = Model::from_igrf(&fs::read_to_string(path)?)?;
+            igrf.extend(Model::wmm().clone());
+            igrf
+        }
+        None => Model::wmm().clone(),
+    };
+    for path in &cli.cof {
+        model.extend(Model::from_cof(&fs::read_to_string(path)?)?);
+    }
+    let model = &model;
+    let csv = CsvFormat::default();
+    let mut out = io::stdout().lock();
+    match cli.command {
+        Command::Info {
+            input,
+            people,
+            json,
+        } => {
+            let cave = read(&input.input, input.from)?;
+            if people {
+                people_info(&mut out, &cave, json)?;
+            } else {
+                info(&mut out, &cave, json, model)?;
+            }
+        }
+        Command::Validate { input } => {
+            let cave = read(&input.input, input.from)?;
+            let problems = problems(&cave);
+            for problem in &problems {
+                writeln!(out, "{}", problem)?;
+            }
+            if !problems.is_empty() {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Command::Convert { input, output, csv } => {
+            let format = csv.format();
+            let from = input.from.or_else(|| Format::from_extension(&input.input));
+            let cave = if from == Some(Format::Csv) {
+                let base = csv.base.as_deref().ok_or("reading CSV needs --base")?;
+                let mut cave = read(base, None)?;
+                read_csv(&read_bytes(&input.input)?[..], &format, &mut cave)?;
+                cave
+            } else {
+                read(&input.input, from)?
+            };
+            write(cave, &output, &format, model)?;
+        }
+        Command::Diff { old, new, json } => {
+            let changes = diff(&read(&old, None)?, &read(&new, None)?);
+            if json {
+                writeln!(out, "{}", serde_json::to_string(&changes)?)?;
+            } else {
+                write!(out, "{}", render_text(&changes))?;
+            }
+            if !changes.is_empty() {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Command::Merge {
+            ours,
+            theirs,
+            base,
+            tie,
+            output,
+        } => {
+            let (ours, theirs) = (read(&ours, None)?, read(&theirs, None)?);
+            if let Some(base) = base {
+                let merged = merge3(&read(&base, None)?, &ours, &theirs, model)?;
+                for (old, new) in &merged.renumbered {
+                    eprintln!("station {} from theirs is now {}", old, new);
+                }
+                for conflict in &merged.conflicts {
+                    eprintln!("conflict: {}", conflict);
+                }
+                let clean = merged.is_clean();
+                write(merged.cave, &output, &csv, model)?;
+                if !clean {
+                    return Ok(ExitCode::from(1));
+                }
+            } else {
+                let merged = merge(ours, theirs, tie, model)?;
+                for conflict in &merged.conflicts {
+                    eprintln!("{}", conflict);
+                }
+                write(merged.cave, &output, &csv, model)?;
+            }
+        }
+        Command::Split {
+            input,
+            section,
+            subtree,
+            output,
+        } => {
+            let cave = read(&input.input, input.from)?;
+            let split = match (section, subtree) {
+                (Some(section), _) => split_section(&cave, &section, model)?,
+                (None, Some(root)) => split_subtree(&cave, root, model)?,
+                (None, None) => unreachable!(),
+            };
+            write(split.cave, &output, &csv, model)?;
+        }
+        Command::Renumber {
+            input,
+            order,
+            output,
+        } => {
+            let mut cave = read(&input.input, input.from)?;
+            let order = match order {
+                RenumberOrder::File => Order::File,
+                RenumberOrder::Traversal => Order::Traversal,
+                RenumberOrder::Sorted => Order::Sorted,
+            };
+            renumber(&mut cave, order);
+            write(cave, &output, &csv, model)?;
+        }
+        Command::Units {
+            input,
+            unit,
+            output,
+        } => {
+            let mut cave = read(&input.input, input.from)?;
+            convert_units(&mut cave, unit)?;
+            write(cave, &output, &csv, model)?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn broken_pipe(e: &(dyn Error + 'static)) -> bool {
+    let io = match e.downcast_ref::<tmlu_rs::error::Error>() {
+        Some(tmlu_rs::error::Error::Io(io)) => Some(io),
+        _ => e.downcast_ref::<io::Error>(),
+    };
+    io.is_some_and(|io| io.kind() == io::ErrorKind::BrokenPipe)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(code) => code,
+        // Like other tools, stop quietly when the reader of stdout is gone
+        Err(e) if broken_pipe(&*e) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("tmlu: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{Error, Result};
+use crate::geomag::{leg_declination, traverse_true_north, uses_magnetic_azimuth, Model};
+use crate::loops::{loops, Loop};
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::traverse::{leg_vector, Point};
+use crate::units::Unit;
+use crate::utils::{format_double, parse_double};
+
+/// A mistake that could have been made when a leg was measured or written down. The values are
+/// what the leg would have been without it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Blunder {
+    /// The leg is left out of the loops, as if all of it was wrong
+    LeftOut,
+    /// The azimuth was read from the wrong end of the compass
+    Reversed { azimuth: f64 },
+    /// Two digits of the azimuth were swapped
+    AzimuthDigits { azimuth: f64 },
+    /// Two digits of the length were swapped
+    LengthDigits { length: f64 },
+    /// The length was measured in feet and written as meters, or the other way around
+    FeetAndMeters { length: f64 },
+}
+
+/// A leg that makes the loops close better when it is corrected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suspect {
+    /// The station at the end of the leg
+    pub id: i32,
+    pub blunder: Blunder,
+    /// The misclosure error of the survey with the leg corrected
+    pub error: f64,
+    /// The CLOSURE rows of the loops the leg is in
+    pub loops: Vec<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Blunders {
+    /// The sum of the squared misclosures of all loops, in the unit of the file squared
+    pub error: f64,
+    /// The CLOSURE rows of the loops that did not close within the tolerance
+    pub bad_loops: Vec<i32>,
+    /// The best explanations first
+    pub suspects: Vec<Suspect>,
+}
+
+impl Suspect {
+    /// The part of the misclosure error that goes away when the leg is corrected, from 0 to 1
+    pub fn improvement(&self, blunders: &Blunders) -> f64 {
+        if blunders.error > 0.0 {
+            1.0 - self.error / blunders.error
+        } else {
+            0.0
+        }
+    }
+}
+
+fn scaled(p: Point, factor: f64) -> Point {
+    Point {
+        x: p.x * factor,
+        y: p.y * factor,
+        z: p.z * factor,
+    }
+}
+
+fn squared(p: Point) -> f64 {
+    p.x * p.x + p.y * p.y + p.z * p.z
+}
+
+// The values `value` could have had before two neighbouring digits were swapped
+fn swapped_digits(value: &str) -> Vec<f64> {
+    let value = value.trim();
+    let mut swapped = Vec::new();
+    let bytes = value.as_bytes();
+    for i in 1..bytes.len() {
+        let (a, b) = (bytes[i - 1], bytes[i]);
+        if a.is_ascii_digit() && b.is_ascii_digit() && a != b {
+            let mut other = bytes.to_vec();
+            other.swap(i - 1, i);
+            // Only ASCII digits were swapped
+            let other = String::from_utf8(other).unwrap();
+            if let Ok(v) = other.parse::<f64>() {
+                swapped.push(v);
+            }
+        }
+    }
+    swapped
+}
+
+// The mistakes worth trying for a leg, with the leg as it would be without them
+fn candidates(srvd: &SurveyData) -> Result<Vec<(Blunder, SurveyData)>> {
+    let id = Some(srvd.id);
+    let azimuth = parse_double(&srvd.azimuth, id, "AZ")?;
+    let length = parse_double(&srvd.length, id, "LG")?;
+    let mut blunders = vec![Blunder::Reversed {
+        azimuth: (azimuth + 180.0) % 360.0,
+    }];
+    for azimuth in swapped_digits(&srvd.azimuth) {
+        if (0.0..=360.0).contains(&azimuth) {
+            blunders.push(Blunder::AzimuthDigits { azimuth });
+        }
+    }
+    for length in swapped_digits(&srvd.length) {
+        blunders.push(Blunder::LengthDigits { length });
+    }
+    // Whichever unit the file is in, the length could have been taken in the other one
+    let feet = Unit::Feet.factor(Unit::Meters);
+    for factor in [feet, 1.0 / feet] {
+        blunders.push(Blunder::FeetAndMeters {
+            length: length * factor,
+        });
+    }
+
+    Ok(blunders
+        .into_iter()
+        .map(|blunder| {
+            let mut corrected = srvd.clone();
+            match blunder {
+                Blunder::Reversed { azimuth } | Blunder::AzimuthDigits { azimuth } => {
+                    corrected.azimuth = format_double(azimuth)
+                }
+                Blunder::LengthDigits { length } | Blunder::FeetAndMeters { length } => {
+                    corrected.length = format_double(length)
+                }
+                Blunder::LeftOut => (),
+            }
+            (blunder, corrected)
+        })
+        .collect())
+}
+
+/// Looks for the legs that best explain why loops do not close. Loops with a misclosure of more
+/// than `tolerance` times their length are bad, and each leg in them is tried left out and with
+/// the common reading mistakes undone: a reversed azimuth, swapped digits and feet taken for
+/// meters. The misclosure error is the sum of the squared misclosures of all loops, so a
+/// correction that opens up a good loop counts against the leg.
+/// Leaving out a leg that is in a single loop always closes that loop, so among explanations
+/// that work equally well, a reading mistake comes before leaving the leg out.
+/// Magnetic azimuths are corrected with `model`
+pub fn blunders(cave: &CaveFile, tolerance: f64, model: &Model) -> Result<Blunders> {
+    let traverse = traverse_true_north(cave, model)?;
+    let loops = loops(cave, &traverse)?;
+    let error: f64 = loops.iter().map(|l| squared(l.misclosure)).sum();
+    let bad: Vec<&Loop> = loops
+        .iter()
+        .filter(|l| l.relative_error() > tolerance)
+        .collect();
+
+    let stations: HashMap<i32, &SurveyData> = cave
+        .data
+        .iter()
+        .filter(|s| s.station_type != "CLOSURE")
+        .map(|s| (s.id, s))
+        .collect();
+    let station = |id: i32| stations.get(&id).copied().ok_or(Error::MissingStation(id));
+    let magnetic = uses_magnetic_azimuth(cave);
+    let vector = |from: &SurveyData, srvd: &SurveyData| -> Result<Point> {
+        let correction = match traverse.start_of(srvd.id) {
+            Some(start) if magnetic => leg_declination(model, srvd, station(start)?)?,
+            _ => 0.0,
+        };
+        leg_vector(from, srvd, correction)
+    };
+
+    // For each leg, the loops it is in and whether it is on the `to` side of them, where it
+    // adds to the misclosure
+    let mut legs: HashMap<i32, Vec<(usize, f64)>> = HashMap::new();
+    for (i, l) in loops.iter().enumerate() {
+        let mut from_side = HashSet::new();
+        let mut id = l.from_id;
+        while let Some(srvd) = stations.get(&id) {
+            if srvd.station_type == "START" || !from_side.insert(id) {
+                break;
+            }
+            id = srvd.from_id;
+        }
+        for leg in &l.legs {
+            let sign = if from_side.contains(leg) { -1.0 } else { 1.0 };
+            legs.entry(*leg).or_default().push((i, sign));
+        }
+    }
+    // The error with `delta` added to the leg
+    let error_with = |sides: &[(usize, f64)], delta: Point| {
+        let changed: f64 = sides
+            .iter()
+            .map(|&(i, sign)| {
+                let m = loops[i].misclosure;
+                squared(m + scaled(delta, sign)) - squared(m)
+            })
+            .sum();
+        (error + changed).max(0.0)
+    };
+
+    let mut suspects = Vec::new();
+    let mut tried = HashSet::new();
+    for leg in bad.iter().flat_map(|l| &l.legs) {
+        if !tried.insert(*leg) {
+            continue;
+        }
+        let srvd = station(*leg)?;
+        let from = station(srvd.from_id)?;
+        let sides = &legs[leg];
+        let closures: Vec<i32> = sides.iter().map(|&(i, _)| loops[i].closure).collect();
+        let mut suspect = |blunder, error| {
+            suspects.push(Suspect {
+                id: *leg,
+                blunder,
+                error,
+                loops: closures.clone(),
+            })
+        };
+
+        // Left out, the leg takes whatever value fits its loops best
+        let sum = sides.iter().fold(Point::default(), |sum, &(i, sign)| {
+            sum + scaled(loops[i].misclosure, sign)
+        });
+        let best = scaled(sum, -1.0 / sides.len() as f64);
+        suspect(Blunder::LeftOut, error_with(sides, best));
+
+        let measured = vector(from, srvd)?;
+        for (blunder, corrected) in candidates(srvd)? {
+            let delta = vector(from, &corrected)? - measured;
+            suspect(blunder, error_with(sides, delta));
+        }
+    }
+    suspects.retain(|s| s.error < error);
+    // Errors that are the same but for rounding rank the same
+    let rank = |s: &Suspect| {
+        (
+            (s.error / error * 1e6).round() as i64,
+            s.blunder == Blunder::LeftOut,
+            s.id,
+        )
+    };
+    suspects.sort_by_key(rank);
+    Ok(Blunders {
+        error,
+        bad_loops: bad.iter().map(|l| l.closure).collect(),
+        suspects,
+    })
+}
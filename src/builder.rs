@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::tmlu::{write_cavefile, CaveFile, CaveFileInfo, SurveyData};
+use crate::traverse::traverse;
+use crate::units::Unit;
+use crate::utils::{format_double, format_rounded, parse_double, Team};
+
+/// A station given by its id or its name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StationRef {
+    Id(i32),
+    Name(String),
+}
+
+impl From<i32> for StationRef {
+    fn from(id: i32) -> StationRef {
+        StationRef::Id(id)
+    }
+}
+
+impl From<&str> for StationRef {
+    fn from(name: &str) -> StationRef {
+        StationRef::Name(name.to_string())
+    }
+}
+
+fn invalid(tag: &str, value: f64) -> Error {
+    Error::InvalidField {
+        id: None,
+        tag: tag.to_string(),
+        value: format_double(value),
+    }
+}
+
+fn check(tag: &str, value: f64, min: f64, max: f64) -> Result<f64> {
+    if value.is_finite() && value >= min && value <= max {
+        Ok(value)
+    } else {
+        Err(invalid(tag, value))
+    }
+}
+
+/// Builds a survey station by station. Ids are given out in order from 0, the stations get
+/// Ariane's default shapes, and the date, section and team set last.
+/// Lengths and depths are in the unit of the file
+#[derive(Debug, Clone)]
+pub struct CaveBuilder {
+    cave: CaveFile,
+    next_id: i32,
+    date: String,
+    section: String,
+    team: Option<Team>,
+}
+
+impl CaveBuilder {
+    pub fn new(cave_name: &str, unit: Unit) -> CaveBuilder {
+        CaveBuilder {
+            cave: CaveFile {
+                info: CaveFileInfo {
+                    cave_name: cave_name.to_string(),
+                    unit: unit.as_str().to_string(),
+                    ..Default::default()
+                },
+                data: Vec::new(),
+            },
+            next_id: 0,
+            date: SurveyData::default().date,
+            section: String::new(),
+            team: None,
+        }
+    }
+
+    /// The date of the stations added after this, as `yyyy-mm-dd`
+    pub fn date(&mut self, date: &str) -> &mut CaveBuilder {
+        self.date = date.to_string();
+        self
+    }
+
+    pub fn section(&mut self, section: &str) -> &mut CaveBuilder {
+        self.section = section.to_string();
+        self
+    }
+
+    pub fn team(&mut self, team: &Team) -> &mut CaveBuilder {
+        self.team = Some(team.clone());
+        self
+    }
+
+    /// For the header fields the builder does not set
+    pub fn info_mut(&mut self) -> &mut CaveFileInfo {
+        &mut self.cave.info
+    }
+
+    /// For the fields of a station the builder does not set, like its walls or comment
+    pub fn station_mut(&mut self, id: i32) -> Option<&mut SurveyData> {
+        self.cave.data.iter_mut().find(|s| s.id == id)
+    }
+
+    /// The id of a station that is not a CLOSURE row
+    pub fn find(&self, station: &StationRef) -> Result<i32> {
+        let mut stations = self
+            .cave
+            .data
+            .iter()
+            .filter(|s| s.station_type != "CLOSURE");
+        match station {
+            StationRef::Id(id) => stations
+                .find(|s| s.id == *id)
+                .map(|s| s.id)
+                .ok_or(Error::MissingStation(*id)),
+            StationRef::Name(name) => stations
+                .find(|s| s.name.as_deref().map(str::trim) == Some(name.trim()))
+                .map(|s| s.id)
+                .ok_or_else(|| Error::UnknownStation(name.clone())),
+        }
+    }
+
+    // Names have to be unique so they can be used to find the station again
+    fn named(&self, srvd: &mut SurveyData, name: &str) -> Result<()> {
+        let name = name.trim();
+        if !name.is_empty() && self.find(&StationRef::from(name)).is_ok() {
+            return Err(Error::InvalidField {
+                id: None,
+                tag: "NM".to_string(),
+                value: name.to_string(),
+            });
+        }
+        // Empty elements are read as `None`
+        srvd.name = Some(name.to_string()).filter(|name| !name.is_empty());
+        srvd.section = Some(self.section.clone()).filter(|section| !section.is_empty());
+        if let Some(team) = &self.team {
+            srvd.set_team(team);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, srvd: SurveyData) -> i32 {
+        let id = srvd.id;
+        self.cave.data.push(srvd);
+        self.next_id += 1;
+        id
+    }
+
+    /// Adds a START station at `latitude` and `longitude` in degrees and `depth` below the
+    /// surface, and returns its id
+    pub fn start(&mut self, name: &str, latitude: f64, longitude: f64, depth: f64) -> Result<i32> {
+        let latitude = check("LT", latitude, -90.0, 90.0)?;
+        let longitude = check("LGT", longitude, -180.0, 180.0)?;
+        let depth = check("DP", depth, f64::MIN, f64::MAX)?;
+        let mut srvd = SurveyData::start(self.next_id, latitude, longitude, &self.date);
+        srvd.depth = format_rounded(depth);
+        srvd.depth_in = srvd.depth.clone();
+        self.named(&mut srvd, name)?;
+        Ok(self.push(srvd))
+    }
+
+    /// Adds a REAL leg of `length` at `azimuth` degrees from `from`, to a new station at `depth`,
+    /// and returns the id of the new station. The inclination follows from the change in depth,
+    /// which can not be more than the length
+    pub fn leg(
+        &mut self,
+        from: impl Into<StationRef>,
+        name: &str,
+        length: f64,
+        azimuth: f64,
+        depth: f64,
+    ) -> Result<i32> {
+        let from_id = self.find(&from.into())?;
+        let length = check("LG", length, 0.0, f64::MAX)?;
+        let azimuth = check("AZ", azimuth, 0.0, 360.0)?;
+        let depth = check("DP", depth, f64::MIN, f64::MAX)?;
+        let from = self.cave.data.iter().find(|s| s.id == from_id).unwrap();
+        let rise = parse_double(&from.depth, Some(from_id), "DP")? - depth;
+        if rise.abs() > length + 1e-9 {
+            return Err(invalid("DP", depth));
+        }
+        let inclination = if length > 0.0 {
+            (rise / length).clamp(-1.0, 1.0).asin().to_degrees()
+        } else {
+            0.0
+        };
+
+        let mut srvd = SurveyData::real(self.next_id, from);
+        srvd.date = self.date.clone();
+        srvd.length = format_rounded(length);
+        srvd.azimuth = format_rounded(azimuth);
+        srvd.inclination = format_rounded(inclination);
+        srvd.depth = format_rounded(depth);
+        self.named(&mut srvd, name)?;
+        Ok(self.push(srvd))
+    }
+
+    /// Adds a CLOSURE row telling that `from` and `to` are the same point, and returns its id
+    pub fn closure(
+        &mut self,
+        from: impl Into<StationRef>,
+        to: impl Into<StationRef>,
+    ) -> Result<i32> {
+        let from_id = self.find(&from.into())?;
+        let to_id = self.find(&to.into())?;
+        if from_id == to_id {
+            return Err(Error::InvalidField {
+                id: None,
+                tag: "CID".to_string(),
+                value: to_id.to_string(),
+            });
+        }
+        let srvd = SurveyData::closure(self.next_id, from_id, to_id, &self.date);
+        Ok(self.push(srvd))
+    }
+
+    /// Checks that there is a START station, that every leg and CLOSURE row refers to a station
+    /// and that the stations can be laid out, and returns the survey
+    pub fn build(self) -> Result<CaveFile> {
+        let cave = self.cave;
+        if !cave.data.iter().any(|s| s.station_type == "START") {
+            return Err(Error::NoStart);
+        }
+        let ids: HashSet<i32> = cave
+            .data
+            .iter()
+            .filter(|s| s.station_type != "CLOSURE")
+            .map(|s| s.id)
+            .collect();
+        for srvd in &cave.data {
+            let refers = match srvd.station_type.as_str() {
+                "START" => vec![],
+                "CLOSURE" => vec![srvd.from_id, srvd.closure_to_id],
+                _ => vec![srvd.from_id],
+            };
+            if let Some(id) = refers.into_iter().find(|id| !ids.contains(id)) {
+                return Err(Error::MissingStation(id));
+            }
+        }
+        cave.info.unit.parse::<Unit>()?;
+        traverse(&cave)?;
+        Ok(cave)
+    }
+
+    /// Builds the survey and writes it as tmlu
+    pub fn write<W: Write>(self, output: W) -> Result<()> {
+        let cave = self.build()?;
+        write_cavefile(output, cave.data, cave.info)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use std::io::{BufWriter, Read, Write};
+use std::mem::take;
+
+use crate::error::{Error, Result};
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::units::Unit;
+use crate::utils::{format_double, format_rounded, parse_double};
+
+/// What a CSV column holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    From,
+    To,
+    Length,
+    Azimuth,
+    Inclination,
+    /// The absolute depth of the `To` station, positive down
+    Depth,
+    Left,
+    Right,
+    Up,
+    Down,
+    Date,
+    Comment,
+    Section,
+    Explorer,
+    /// REAL or VIRTUAL
+    Type,
+    Ignore,
+}
+
+impl Column {
+    /// The name written in the header
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::From => "From",
+            Column::To => "To",
+            Column::Length => "Length",
+            Column::Azimuth => "Azimuth",
+            Column::Inclination => "Inclination",
+            Column::Depth => "Depth",
+            Column::Left => "Left",
+            Column::Right => "Right",
+            Column::Up => "Up",
+            Column::Down => "Down",
+            Column::Date => "Date",
+            Column::Comment => "Comment",
+            Column::Section => "Section",
+            Column::Explorer => "Explorer",
+            Column::Type => "Type",
+            Column::Ignore => "",
+        }
+    }
+
+    /// The column a header cell names, ignoring case and a unit in parentheses like
+    /// `Length (m)`
+    pub fn from_header(cell: &str) -> Option<Column> {
+        let cell = cell.trim().to_lowercase();
+        let name = cell
+            .split_once('(')
+            .map_or(&cell[..], |(name, _)| name)
+            .trim();
+        let column = match name {
+            "from" | "from station" | "station from" => Column::From,
+            "to" | "to station" | "station to" | "station" => Column::To,
+            "length" | "distance" | "dist" | "tape" | "lg" => Column::Length,
+            "azimuth" | "az" | "bearing" | "compass" => Column::Azimuth,
+            "inclination" | "inc" | "clino" | "dip" => Column::Inclination,
+            "depth" | "dp" => Column::Depth,
+            "left" | "l" => Column::Left,
+            "right" | "r" => Column::Right,
+            "up" | "u" => Column::Up,
+            "down" | "d" => Column::Down,
+            "date" | "dt" => Column::Date,
+            "comment" | "comments" | "note" | "notes" | "cm" => Column::Comment,
+            "section" | "sc" => Column::Section,
+            "explorer" | "explorers" | "team" | "ex" => Column::Explorer,
+            "type" | "ty" => Column::Type,
+            _ => return None,
+        };
+        Some(column)
+    }
+}
+
+/// How the `From` and `To` stations are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Stations {
+    /// By `NM`. When reading, a `From` no station is named is looked up as an id. A `To` no
+    /// station is named, but that is the id of one, is an error, as it could be a new station
+    /// or a closure. When writing, stations without a unique name are written by id
+    #[default]
+    Names,
+    Ids,
+}
+
+/// Whether the first row is a header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Header {
+    /// The first row is a header if each of its cells names a column
+    #[default]
+    Detect,
+    /// The first row is a header. Its cells set the columns, cells naming no column are ignored
+    Present,
+    /// The first row is a header, but the columns are the configured ones
+    Skip,
+    Absent,
+}
+
+/// The layout of a CSV file of survey shots
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFormat {
+    /// The columns in order, replaced by the header when there is one
+    pub columns: Vec<Column>,
+    pub delimiter: char,
+    /// Numbers are written `1,5`
+    pub decimal_comma: bool,
+    pub header: Header,
+    pub stations: Stations,
+    /// The unit of lengths and depths, the unit of the file if `None`
+    pub unit: Option<Unit>,
+}
+
+impl Default for CsvFormat {
+    fn default() -> CsvFormat {
+        CsvFormat {
+            columns: vec![
+                Column::From,
+                Column::To,
+                Column::Length,
+                Column::Azimuth,
+                Column::Inclination,
+                Column::Depth,
+                Column::Left,
+                Column::Right,
+                Column::Up,
+                Column::Down,
+                Column::Date,
+                Column::Comment,
+            ],
+            delimiter: ',',
+            decimal_comma: false,
+            header: Header::default(),
+            stations: Stations::default(),
+            unit: None,
+        }
+    }
+}
+
+impl CsvFormat {
+    /// Semicolons between cells and `1,5` for numbers, as spreadsheets write them in most of
+    /// Europe
+    pub fn decimal_comma() -> CsvFormat {
+        CsvFormat {
+            delimiter: ';',
+            decimal_comma: true,
+            ..Default::default()
+        }
+    }
+}
+
+// The records of a CSV text with the line they start on. Quoted cells can hold delimiters,
+// line breaks and `""` for a quote
+fn records(text: &str, delimiter: char) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut records = Vec::new();
+    let (mut cells, mut cell) = (Vec::new(), String::new());
+    let (mut line, mut start) = (1, 1);
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    cell.push('"');
+                }
+                '"' => quoted = false,
+                '\n' => {
+                    line += 1;
+                    cell.push(c);
+                }
+                _ => cell.push(c),
+            }
+        } else if c == '"' && cell.trim().is_empty() {
+            quoted = true;
+            cell.clear();
+        } else if c == delimiter {
+            cells.push(take(&mut cell));
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            cells.push(take(&mut cell));
+            records.push((start, take(&mut cells)));
+            line += 1;
+            start = line;
+        } else {
+            cell.push(c);
+        }
+    }
+    if quoted {
+        return Err(Error::Csv {
+            line: start,
+            message: "Quoted cell is not closed".to_string(),
+        });
+    }
+    if !cell.is_empty() || !cells.is_empty() {
+        cells.push(cell);
+        records.push((start, cells));
+    }
+    Ok(records)
+}
+
+// One data row, with the columns it is read with
+struct Row<'a> {
+    line: usize,
+    cells: &'a [String],
+    columns: &'a [Column],
+    decimal_comma: bool,
+}
+
+impl Row<'_> {
+    fn error(&self, message: String) -> Error {
+        Error::Csv {
+            line: self.line,
+            message,
+        }
+    }
+
+    // The trimmed cell of `column`, empty if the row has no such cell
+    fn text(&self, column: Column) -> &str {
+        self.columns
+            .iter()
+            .position(|c| *c == column)
+            .and_then(|i| self.cells.get(i))
+            .map_or("", |cell| cell.trim())
+    }
+
+    fn number(&self, column: Column) -> Result<Option<f64>> {
+        let text = self.text(column);
+        if text.is_empty() {
+            return Ok(None);
+        }
+        let value = if self.decimal_comma {
+            text.replace(',', ".").parse::<f64>()
+        } else {
+            text.parse::<f64>()
+        };
+        match value {
+            Ok(value) if value.is_finite() => Ok(Some(value)),
+            _ => Err(self.error(format!("{} is not a number: {}", column.name(), text))),
+        }
+    }
+
+    fn required(&self, column: Column) -> Result<f64> {
+        self.number(column)?
+            .ok_or_else(|| self.error(format!("{} is missing", column.name())))
+    }
+
+    fn within(&self, column: Column, value: f64, min: f64, max: f64) -> Result<f64> {
+        if value < min || value > max {
+            return Err(self.error(format!(
+                "{} is not between {} and {}: {}",
+                column.name(),
+                min,
+                max,
+                value
+            )));
+        }
+        Ok(value)
+    }
+}
+
+// The index of the station `label` stands for, if there is one
+fn find(data: &[SurveyData], label: &str, stations: Stations, row: &Row) -> Result<Option<usize>> {
+    let stations_only = || {
+        data.iter()
+            .enumerate()
+            .filter(|(_, s)| s.station_type != "CLOSURE")
+    };
+    let by_id = |id: i32| stations_only().find(|(_, s)| s.id == id).map(|(i, _)| i);
+    match stations {
+        Stations::Ids => {
+            let id = label
+                .parse::<i32>()
+                .map_err(|_| row.error(format!("Station id is not a number: {}", label)))?;
+            Ok(by_id(id))
+        }
+        Stations::Names => {
+            let mut named =
+                stations_only().filter(|(_, s)| s.name.as_deref().map(str::trim) == Some(label));
+            match (named.next(), named.next()) {
+                (Some(_), Some(_)) => {
+                    Err(row.error(format!("More than one station is named {}", label)))
+                }
+                (Some((i, _)), None) => Ok(Some(i)),
+                _ => Ok(label.parse::<i32>().ok().and_then(by_id)),
+            }
+        }
+    }
+}
+
+/// Reads survey shots from CSV and appends them to `cave`, returning the id of the station
+/// added for each row. Each row is a leg from an existing station, or one added by an earlier
+/// row, to a new station. A `To` that is already a station gets a new station closed onto it
+/// with a CLOSURE row, and an empty `To` gets the next free id.
+/// `From`, `Length` and `Azimuth` are needed. Without a depth, the depth follows from the
+/// inclination. The new stations take the date, section, team and color of their `From` unless
+/// the row has them.
+/// Nothing is added if a row is invalid, and the error has its line
+pub fn read_csv<R: Read>(
+    mut input: R,
+    format: &CsvFormat,
+    cave: &mut CaveFile,
+) -> Result<Vec<i32>> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+    let mut rows = records(text, format.delimiter)?
+        .into_iter()
+        .filter(|(_, cells)| cells.iter().any(|cell| !cell.trim().is_empty()))
+        .peekable();
+
+    let mut columns = format.columns.clone();
+    let mut header_line = 1;
+    if let Some((line, first)) = rows.peek() {
+        let named: Vec<Option<Column>> = first.iter().map(|c| Column::from_header(c)).collect();
+        let is_header = match format.header {
+            Header::Absent => false,
+            Header::Present | Header::Skip => true,
+            Header::Detect => first
+                .iter()
+                .zip(&named)
+                .all(|(cell, column)| cell.trim().is_empty() || column.is_some()),
+        };
+        if is_header {
+            header_line = *line;
+            if format.header != Header::Skip {
+                columns = named
+                    .into_iter()
+                    .map(|c| c.unwrap_or(Column::Ignore))
+                    .collect();
+            }
+            rows.next();
+        }
+    }
+    for column in [Column::From, Column::Length, Column::Azimuth] {
+        if !columns.contains(&column) {
+            return Err(Error::Csv {
+                line: header_line,
+                message: format!("There is no {} column", column.name()),
+            });
+        }
+    }
+
+    let unit: Unit = cave.info.unit.parse()?;
+    let factor = format.unit.unwrap_or(unit).factor(unit);
+    // Works on a copy so an invalid row leaves `cave` untouched
+    let mut data = cave.data.clone();
+    let mut next_id = data.iter().map(|s| s.id + 1).max().unwrap_or(0);
+    let mut added = Vec::new();
+    for (line, cells) in rows {
+        let row = Row {
+            line,
+            cells: &cells,
+            columns: &columns,
+            decimal_comma: format.decimal_comma,
+        };
+        if cells[columns.len().min(cells.len())..]
+            .iter()
+            .any(|cell| !cell.trim().is_empty())
+        {
+            return Err(row.error(format!(
+                "Expected at most {} cells, found {}",
+                columns.len(),
+                cells.len()
+            )));
+        }
+
+        let from_label = row.text(Column::From);
+        if from_label.is_empty() {
+            return Err(row.error("From is missing".to_string()));
+        }
+        let from = find(&data, from_label, format.stations, &row)?
+            .map(|i| &data[i])
+            .ok_or_else(|| row.error(format!("No station {}", from_label)))?;
+
+        let length = row.required(Column::Length)?;
+        if length < 0.0 {
+            return Err(row.error(format!("Length can not be negative: {}", length)));
+        }
+        let length = length * factor;
+        let azimuth = row.required(Column::Azimuth)?;
+        let azimuth = row.within(Column::Azimuth, azimuth, 0.0, 360.0)?;
+        let inclination = row.number(Column::Inclination)?.unwrap_or(0.0);
+        let inclination = row.within(Column::Inclination, inclination, -90.0, 90.0)?;
+        let depth = match row.number(Column::Depth)? {
+            Some(depth) => depth * factor,
+            None => {
+                parse_double(&from.depth, Some(from.id), "DP")?
+                    - length * inclination.to_radians().sin()
+            }
+        };
+
+        let to_label = row.text(Column::To);
+        let closed_onto = if to_label.is_empty() {
+            None
+        } else {
+            find(&data, to_label, format.stations, &row)?
+                .map(|i| &data[i])
+                .map(|to| match format.stations {
+                    Stations::Names if to.name.as_deref().map(str::trim) != Some(to_label) => {
+                        Err(row.error(format!(
+                            "No station is named {0}, but one has the id {0}. Close onto it \
+                             with ids",
+                            to_label
+                        )))
+                    }
+                    _ => Ok(to.id),
+                })
+                .transpose()?
+        };
+        if closed_onto == Some(from.id) {
+            return Err(row.error("From and To are the same station".to_string()));
+        }
+        let id = match (format.stations, closed_onto) {
+            (Stations::Ids, None) if !to_label.is_empty() => {
+                // `find` made sure it is a number
+                let id = to_label.parse::<i32>().unwrap();
+                // `find` only looks at stations, CLOSURE rows have ids too
+                if data.iter().any(|s| s.id == id) {
+                    return Err(row.error(format!("Station id {} is already taken", id)));
+                }
+                id
+            }
+            _ => next_id,
+        };
+        next_id = next_id.max(id + 1);
+
+        let mut srvd = SurveyData::real(id, from);
+        srvd.length = format_rounded(length);
+        srvd.azimuth = format_double(azimuth);
+        srvd.inclination = format_double(inclination);
+        srvd.depth = format_rounded(depth);
+        for (column, value) in [
+            (Column::Left, &mut srvd.left),
+            (Column::Right, &mut srvd.right),
+            (Column::Up, &mut srvd.up),
+            (Column::Down, &mut srvd.down),
+        ] {
+            if let Some(v) = row.number(column)? {
+                if v < 0.0 {
+                    return Err(row.error(format!("{} can not be negative: {}", column.name(), v)));
+                }
+                *value = format_rounded(v * factor);
+            }
+        }
+        if format.stations == Stations::Names && closed_onto.is_none() {
+            srvd.name = Some(to_label.to_string()).filter(|name| !name.is_empty());
+        }
+        let date = row.text(Column::Date);
+        if !date.is_empty() {
+            srvd.date = date.to_string();
+        }
+        for (column, value) in [
+            (Column::Comment, &mut srvd.comment),
+            (Column::Section, &mut srvd.section),
+            (Column::Explorer, &mut srvd.explorer),
+        ] {
+            let text = row.text(column);
+            if !text.is_empty() {
+                *value = Some(text.to_string());
+            }
+        }
+        match row.text(Column::Type).to_uppercase().as_str() {
+            "" | "REAL" => (),
+            "VIRTUAL" => srvd.station_type = "VIRTUAL".to_string(),
+            other => return Err(row.error(format!("Type is not REAL or VIRTUAL: {}", other))),
+        }
+
+        let date = srvd.date.clone();
+        data.push(srvd);
+        added.push(id);
+        if let Some(to_id) = closed_onto {
+            data.push(SurveyData::closure(next_id, id, to_id, &date));
+            next_id += 1;
+        }
+    }
+    cave.data = data;
+    Ok(added)
+}
+
+fn write_row<W: Write>(output: &mut W, cells: &[String], delimiter: char) -> Result<()> {
+    let quote = |cell: &String| {
+        if cell.contains([delimiter, '"', '\n', '\r']) {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        } else {
+            cell.clone()
+        }
+    };
+    let cells: Vec<String> = cells.iter().map(quote).collect();
+    writeln!(output, "{}", cells.join(&delimiter.to_string()))?;
+    Ok(())
+}
+
+/// Writes the REAL and VIRTUAL legs of `cave` as CSV, one row per leg in file order.
+/// Numbers are written as they are in the file unless they are converted to another unit
+pub fn write_csv<W: Write>(output: W, cave: &CaveFile, format: &CsvFormat) -> Result<()> {
+    let unit: Unit = cave.info.unit.parse()?;
+    let factor = unit.factor(format.unit.unwrap_or(unit));
+    let mut output = BufWriter::new(output);
+
+    let stations: HashMap<i32, &SurveyData> = cave
+        .data
+        .iter()
+        .filter(|s| s.station_type != "CLOSURE")
+        .map(|s| (s.id, s))
+        .collect();
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    for srvd in stations.values() {
+        if let Some(name) = srvd.name.as_deref().map(str::trim) {
+            *names.entry(name).or_default() += 1;
+        }
+    }
+    let label = |srvd: &SurveyData| match (format.stations, srvd.name.as_deref().map(str::trim)) {
+        (Stations::Names, Some(name)) if !name.is_empty() && names[name] == 1 => name.to_string(),
+        _ => srvd.id.to_string(),
+    };
+    let number = |value: &str, converted: bool, id: i32, tag: &str| -> Result<String> {
+        let value = if converted && factor != 1.0 {
+            format_rounded(parse_double(value, Some(id), tag)? * factor)
+        } else {
+            value.trim().to_string()
+        };
+        Ok(if format.decimal_comma {
+            value.replace('.', ",")
+        } else {
+            value
+        })
+    };
+
+    if format.header != Header::Absent {
+        let header: Vec<String> = format
+            .columns
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect();
+        write_row(&mut output, &header, format.delimiter)?;
+    }
+    for srvd in &cave.data {
+        if !matches!(srvd.station_type.as_str(), "REAL" | "VIRTUAL") {
+            continue;
+        }
+        let from = stations
+            .get(&srvd.from_id)
+            .ok_or(Error::MissingStation(srvd.from_id))?;
+        let id = srvd.id;
+        let mut cells = Vec::with_capacity(format.columns.len());
+        for column in &format.columns {
+            cells.push(match column {
+                Column::From => label(from),
+                Column::To => label(srvd),
+                Column::Length => number(&srvd.length, true, id, "LG")?,
+                Column::Azimuth => number(&srvd.azimuth, false, id, "AZ")?,
+                Column::Inclination => number(&srvd.inclination, false, id, "INC")?,
+                Column::Depth => number(&srvd.depth, true, id, "DP")?,
+                Column::Left => number(&srvd.left, true, id, "L")?,
+                Column::Right => number(&srvd.right, true, id, "R")?,
+                Column::Up => number(&srvd.up, true, id, "U")?,
+                Column::Down => number(&srvd.down, true, id, "D")?,
+                Column::Date => srvd.date.trim().to_string(),
+                Column::Comment => srvd.comment.clone().unwrap_or_default(),
+                Column::Section => srvd.section.clone().unwrap_or_default(),
+                Column::Explorer => srvd.explorer.clone().unwrap_or_default(),
+                Column::Type => srvd.station_type.trim().to_string(),
+                Column::Ignore => String::new(),
+            });
+        }
+        write_row(&mut output, &cells, format.delimiter)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::records;
+
+    #[test]
+    fn quoted_cells() {
+        let text = "a,\"b, \"\"c\"\"\",d\r\n\"two\nlines\",e\n\nf";
+        let rows = records(text, ',').unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    1,
+                    vec!["a".to_string(), "b, \"c\"".to_string(), "d".to_string()]
+                ),
+                (2, vec!["two\nlines".to_string(), "e".to_string()]),
+                (4, vec![String::new()]),
+                (5, vec!["f".to_string()]),
+            ]
+        );
+        assert!(records("a,\"b\n", ',').is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::tmlu::{CaveFile, Shape, SurveyData};
+
+/// One difference between two versions of a cave file. Fields are named by their tmlu tags
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "change", rename_all = "camelCase"))]
+pub enum Change {
+    Header {
+        tag: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    Added {
+        id: i32,
+        name: Option<String>,
+    },
+    Removed {
+        id: i32,
+        name: Option<String>,
+    },
+    /// The station is now surveyed from another station
+    Moved {
+        id: i32,
+        #[cfg_attr(feature = "serde", serde(rename = "old"))]
+        old_from: i32,
+        #[cfg_attr(feature = "serde", serde(rename = "new"))]
+        new_from: i32,
+    },
+    /// A length, angle or depth changed
+    Measurement {
+        id: i32,
+        tag: &'static str,
+        old: String,
+        new: String,
+    },
+    /// Any other field of a station changed
+    Attribute {
+        id: i32,
+        tag: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+impl Change {
+    /// The station the change is about, `None` for header changes
+    pub fn id(&self) -> Option<i32> {
+        match self {
+            Change::Header { .. } => None,
+            Change::Added { id, .. }
+            | Change::Removed { id, .. }
+            | Change::Moved { id, .. }
+            | Change::Measurement { id, .. }
+            | Change::Attribute { id, .. } => Some(*id),
+        }
+    }
+}
+
+fn quoted(value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("{:?}", value),
+        None => "none".to_string(),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |name: &Option<String>| match name.as_deref() {
+            Some(name) if !name.is_empty() => format!(" ({})", name),
+            _ => String::new(),
+        };
+        match self {
+            Change::Header { tag, old, new } => {
+                write!(f, "~ {}: {} -> {}", tag, quoted(old), quoted(new))
+            }
+            Change::Added { id, name: n } => write!(f, "+ station {}{} added", id, name(n)),
+            Change::Removed { id, name: n } => write!(f, "- station {}{} removed", id, name(n)),
+            Change::Moved {
+                id,
+                old_from,
+                new_from,
+            } => write!(
+                f,
+                "~ station {} now from {} (was {})",
+                id, new_from, old_from
+            ),
+            Change::Measurement { id, tag, old, new } => {
+                write!(f, "~ station {} {}: {} -> {}", id, tag, old, new)
+            }
+            Change::Attribute { id, tag, old, new } => write!(
+                f,
+                "~ station {} {}: {} -> {}",
+                id,
+                tag,
+                quoted(old),
+                quoted(new)
+            ),
+        }
+    }
+}
+
+/// One change per line
+pub fn render_text(changes: &[Change]) -> String {
+    changes.iter().map(|c| format!("{}\n", c)).collect()
+}
+
+// Numbers that parse to the same value, like 10 and 10.0, are not a change
+fn same_number(old: &str, new: &str) -> bool {
+    match (old.trim().parse::<f64>(), new.trim().parse::<f64>()) {
+        (Ok(old), Ok(new)) => old == new,
+        _ => old == new,
+    }
+}
+
+fn shape_summary(shape: &Shape) -> String {
+    let radii: Vec<String> = shape
+        .radius_collection
+        .iter()
+        .map(|rv| format!("{}@{}", rv.length, rv.angle))
+        .collect();
+    let mut summary = radii.join(" ");
+    if shape.has_profile_azimut == "true" {
+        summary.push_str(&format!(" azimut {}", shape.profile_azimut));
+    }
+    if shape.has_profile_tilt == "true" {
+        summary.push_str(&format!(" tilt {}", shape.profile_tilt));
+    }
+    summary
+}
+
+fn station_changes(old: &SurveyData, new: &SurveyData, changes: &mut Vec<Change>) {
+    let id = new.id;
+    if old.from_id != new.from_id {
+        changes.push(Change::Moved {
+            id,
+            old_from: old.from_id,
+            new_from: new.from_id,
+        });
+    }
+    let measurements = [
+        ("LG", &old.length, &new.length),
+        ("AZ", &old.azimuth, &new.azimuth),
+        ("INC", &old.inclination, &new.inclination),
+        ("DP", &old.depth, &new.depth),
+        ("DPI", &old.depth_in, &new.depth_in),
+        ("L", &old.left, &new.left),
+        ("R", &old.right, &new.right),
+        ("U", &old.up, &new.up),
+        ("D", &old.down, &new.down),
+    ];
+    for (tag, a, b) in measurements {
+        if !same_number(a, b) {
+            changes.push(Change::Measurement {
+                id,
+                tag,
+                old: a.clone(),
+                new: b.clone(),
+            });
+        }
+    }
+    if old.shape != new.shape {
+        changes.push(Change::Measurement {
+            id,
+            tag: "SH",
+            old: shape_summary(&old.shape),
+            new: shape_summary(&new.shape),
+        });
+    }
+
+    let text = |value: &String| Some(value.clone());
+    let attributes = [
+        ("NM", old.name.clone(), new.name.clone()),
+        ("CM", old.comment.clone(), new.comment.clone()),
+        ("SC", old.section.clone(), new.section.clone()),
+        ("EX", old.explorer.clone(), new.explorer.clone()),
+        ("DT", text(&old.date), text(&new.date)),
+        ("TY", text(&old.station_type), text(&new.station_type)),
+        (
+            "CID",
+            Some(old.closure_to_id.to_string()),
+            Some(new.closure_to_id.to_string()),
+        ),
+        ("EXC", text(&old.excluded), text(&new.excluded)),
+        ("LK", text(&old.locked), text(&new.locked)),
+        ("CL", text(&old.color), text(&new.color)),
+        ("PRTY", text(&old.profile_type), text(&new.profile_type)),
+        ("LT", text(&old.latitude), text(&new.latitude)),
+        ("LGT", text(&old.longitude), text(&new.longitude)),
+    ];
+    for (tag, a, b) in attributes {
+        let numeric = matches!(tag, "LT" | "LGT");
+        let same = match (&a, &b) {
+            (Some(a), Some(b)) if numeric => same_number(a, b),
+            // Ariane writes an empty element for no value
+            _ => a.as_deref().unwrap_or_default() == b.as_deref().unwrap_or_default(),
+        };
+        if !same {
+            changes.push(Change::Attribute {
+                id,
+                tag,
+                old: a,
+                new: b,
+            });
+        }
+    }
+}
+
+/// The changes from `old` to `new`, header changes first, then by station id
+pub fn diff(old: &CaveFile, new: &CaveFile) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let (a, b) = (&old.info, &new.info);
+    let headers = [
+        ("caveName", Some(&a.cave_name), Some(&b.cave_name)),
+        ("unit", Some(&a.unit), Some(&b.unit)),
+        (
+            "useMagneticAzimuth",
+            Some(&a.use_magnetic_azimuth),
+            Some(&b.use_magnetic_azimuth),
+        ),
+        (
+            "firstStartAbsoluteElevation",
+            Some(&a.first_start_absolute_elevation),
+            Some(&b.first_start_absolute_elevation),
+        ),
+        ("geoCoding", a.geo_coding.as_ref(), b.geo_coding.as_ref()),
+    ];
+    for (tag, old, new) in headers {
+        if old != new {
+            changes.push(Change::Header {
+                tag,
+                old: old.cloned(),
+                new: new.cloned(),
+            });
+        }
+    }
+    let names = |names: Vec<&str>| Some(names.join(", "));
+    if a.annotations != b.annotations {
+        changes.push(Change::Header {
+            tag: "ListAnnotation",
+            old: Some(a.annotations.len().to_string()),
+            new: Some(b.annotations.len().to_string()),
+        });
+    }
+    if a.layers != b.layers {
+        changes.push(Change::Header {
+            tag: "Layers",
+            old: names(a.layers.iter().map(|l| l.name.as_str()).collect()),
+            new: names(b.layers.iter().map(|l| l.name.as_str()).collect()),
+        });
+    }
+    if a.carto_overlay != b.carto_overlay {
+        changes.push(Change::Header {
+            tag: "CartoOverlay",
+            old: names(
+                a.carto_overlay
+                    .iter()
+                    .map(|o| o.file_name.as_str())
+                    .collect(),
+            ),
+            new: names(
+                b.carto_overlay
+                    .iter()
+                    .map(|o| o.file_name.as_str())
+                    .collect(),
+            ),
+        });
+    }
+    if a.carto_linked_surface != b.carto_linked_surface {
+        let surfaces = |info: &crate::tmlu::CaveFileInfo| {
+            names(
+                info.carto_linked_surface
+                    .iter()
+                    .map(|s| s.file_name.as_str())
+                    .collect(),
+            )
+        };
+        changes.push(Change::Header {
+            tag: "CartoLinkedSurface",
+            old: surfaces(a),
+            new: surfaces(b),
+        });
+    }
+
+    let before: BTreeMap<i32, &SurveyData> = old.data.iter().map(|s| (s.id, s)).collect();
+    let after: BTreeMap<i32, &SurveyData> = new.data.iter().map(|s| (s.id, s)).collect();
+    let mut ids: Vec<i32> = before.keys().chain(after.keys()).copied().collect();
+    ids.sort_unstable();
+    ids.dedup();
+    for id in ids {
+        match (before.get(&id), after.get(&id)) {
+            (Some(old), Some(new)) => station_changes(old, new, &mut changes),
+            (Some(old), None) => changes.push(Change::Removed {
+                id,
+                name: old.name.clone(),
+            }),
+            (None, Some(new)) => changes.push(Change::Added {
+                id,
+                name: new.name.clone(),
+            }),
+            (None, None) => (),
+        }
+    }
+    changes
+}
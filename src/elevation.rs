@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::Result;
+use crate::tmlu::{CaveFile, Shape, SurveyData};
+use crate::traverse::leg_vector;
+use crate::utils::parse_double;
+
+/// Which way the legs are laid out on the profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Right,
+    Left,
+}
+
+impl Direction {
+    pub fn flip(self) -> Direction {
+        match self {
+            Direction::Right => Direction::Left,
+            Direction::Left => Direction::Right,
+        }
+    }
+
+    fn sign(self) -> f64 {
+        match self {
+            Direction::Right => 1.0,
+            Direction::Left => -1.0,
+        }
+    }
+}
+
+/// A station on an extended elevation, in the unit of the file. `x` runs along the unfolded
+/// legs and `z` is up, with 0 at the surface
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileStation {
+    pub id: i32,
+    /// -1 for START stations
+    pub from_id: i32,
+    pub x: f64,
+    pub z: f64,
+    pub direction: Direction,
+    /// The ends of the passage section as drawn on the profile. Up and down for a VERTICAL
+    /// profile, behind and ahead (left and right) for a HORIZONTAL one
+    pub walls: [(f64, f64); 2],
+}
+
+fn angle(has: &str, value: &str, id: i32, tag: &str) -> Result<Option<f64>> {
+    if has.trim() == "true" {
+        Ok(Some(parse_double(value, Some(id), tag)?.to_radians()))
+    } else {
+        Ok(None)
+    }
+}
+
+// The passage section seen from the side, around the station at (x, z). `heading` is the
+// azimuth of the leg in radians
+fn walls(
+    srvd: &SurveyData,
+    (x, z): (f64, f64),
+    heading: f64,
+    direction: Direction,
+) -> Result<[(f64, f64); 2]> {
+    let id = Some(srvd.id);
+    let Shape {
+        has_profile_azimut,
+        has_profile_tilt,
+        profile_azimut,
+        profile_tilt,
+        ..
+    } = &srvd.shape;
+    let azimut = angle(has_profile_azimut, profile_azimut, srvd.id, "PRAZ")?;
+    let tilt = angle(has_profile_tilt, profile_tilt, srvd.id, "PRT")?.unwrap_or(0.0);
+
+    // (along the profile, up) before tilting
+    let ends = if srvd.profile_type.trim() == "HORIZONTAL" {
+        // A horizontal section, like in a shaft. Its left and right walls lie across the
+        // profile azimut, and only the part along the leg shows
+        let across = azimut.map_or(1.0, |azimut| (heading - azimut).sin().abs());
+        [
+            (-parse_double(&srvd.left, id, "L")? * across, 0.0),
+            (parse_double(&srvd.right, id, "R")? * across, 0.0),
+        ]
+    } else {
+        [
+            (0.0, parse_double(&srvd.up, id, "U")?),
+            (0.0, -parse_double(&srvd.down, id, "D")?),
+        ]
+    };
+    let (sin, cos) = tilt.sin_cos();
+    let sign = direction.sign();
+    Ok(ends.map(|(along, up)| {
+        (
+            x + sign * (along * cos + up * sin),
+            z + up * cos - along * sin,
+        )
+    }))
+}
+
+/// Lays out the survey as an extended elevation: every leg is drawn with its horizontal length
+/// along `x` and its change in depth on `z`, following the legs out from each START station.
+/// The legs go right, and turn around at the stations in `flips`, with everything surveyed
+/// from them. Each START after the first begins where the profile reached furthest right.
+/// Stations come in the order they were reached. CLOSURE rows and stations that can not be
+/// reached from a START are left out
+pub fn extended_elevation(cave: &CaveFile, flips: &HashSet<i32>) -> Result<Vec<ProfileStation>> {
+    let mut children: HashMap<i32, Vec<&SurveyData>> = HashMap::new();
+    for srvd in &cave.data {
+        if !matches!(srvd.station_type.as_str(), "START" | "CLOSURE") {
+            children.entry(srvd.from_id).or_default().push(srvd);
+        }
+    }
+    let flip = |id: i32, direction: Direction| {
+        if flips.contains(&id) {
+            direction.flip()
+        } else {
+            direction
+        }
+    };
+
+    let mut profile: Vec<ProfileStation> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = Vec::new();
+    for start in cave.data.iter().filter(|s| s.station_type == "START") {
+        if !seen.insert(start.id) {
+            continue;
+        }
+        let x = profile.iter().map(|s| s.x).fold(0.0, f64::max);
+        let z = -parse_double(&start.depth, Some(start.id), "DP")?;
+        let direction = flip(start.id, Direction::Right);
+        let heading = parse_double(&start.azimuth, Some(start.id), "AZ")?.to_radians();
+        profile.push(ProfileStation {
+            id: start.id,
+            from_id: -1,
+            x,
+            z,
+            direction,
+            walls: walls(start, (x, z), heading, direction)?,
+        });
+
+        stack.push((start, x, z, direction));
+        while let Some((from, x, z, direction)) = stack.pop() {
+            let depth = stack.len();
+            for srvd in children.get(&from.id).into_iter().flatten() {
+                if !seen.insert(srvd.id) {
+                    continue;
+                }
+                let leg = leg_vector(from, srvd, 0.0)?;
+                let direction = flip(srvd.id, direction);
+                let (x, z) = (x + direction.sign() * leg.horizontal_length(), z + leg.z);
+                let heading = parse_double(&srvd.azimuth, Some(srvd.id), "AZ")?.to_radians();
+                profile.push(ProfileStation {
+                    id: srvd.id,
+                    from_id: from.id,
+                    x,
+                    z,
+                    direction,
+                    walls: walls(srvd, (x, z), heading, direction)?,
+                });
+                stack.push((srvd, x, z, direction));
+            }
+            // So the branches are followed in file order
+            stack[depth..].reverse();
+        }
+    }
+    Ok(profile)
+}
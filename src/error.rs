@@ -0,0 +1,134 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Xml {
+        position: usize,
+        error: quick_xml::Error,
+    },
+    InvalidValue {
+        position: usize,
+        tag: String,
+        value: String,
+    },
+    /// A value in an already parsed file that is not what the operation needs
+    InvalidField {
+        id: Option<i32>,
+        tag: String,
+        value: String,
+    },
+    UnknownUnit(String),
+    /// The operation needs a START station to anchor the survey
+    NoStart,
+    MissingStation(i32),
+    UnknownStation(String),
+    UnknownSection(String),
+    /// The date of a leg is not within the years the geomagnetic model is valid for
+    OutsideModel {
+        id: i32,
+        date: String,
+    },
+    /// A row of a CSV file that could not be read, `line` counts from 1
+    Csv {
+        line: usize,
+        message: String,
+    },
+    #[cfg(feature = "tml")]
+    Zip(zip::result::ZipError),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Xml { position, error } => {
+                write!(f, "Error at position {}: {}", position, error)
+            }
+            Error::InvalidValue {
+                position,
+                tag,
+                value,
+            } => write!(
+                f,
+                "Invalid value '{}' in <{}> at position {}",
+                value, tag, position
+            ),
+            Error::InvalidField {
+                id: Some(id),
+                tag,
+                value,
+            } => write!(
+                f,
+                "Invalid value '{}' in <{}> of station {}",
+                value, tag, id
+            ),
+            Error::InvalidField {
+                id: None,
+                tag,
+                value,
+            } => write!(f, "Invalid value '{}' in <{}>", value, tag),
+            Error::UnknownUnit(unit) => write!(f, "Unknown unit '{}'", unit),
+            Error::NoStart => write!(f, "No START station"),
+            Error::MissingStation(id) => write!(f, "No station with id {}", id),
+            Error::UnknownStation(name) => write!(f, "No station named '{}'", name),
+            Error::UnknownSection(name) => write!(f, "No station in section '{}'", name),
+            Error::OutsideModel { id, date } => write!(
+                f,
+                "The geomagnetic model does not cover {} of station {}",
+                date, id
+            ),
+            Error::Csv { line, message } => write!(f, "Line {}: {}", line, message),
+            #[cfg(feature = "tml")]
+            Error::Zip(e) => write!(f, "{}", e),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Xml { error, .. } => Some(error),
+            Error::InvalidValue { .. }
+            | Error::InvalidField { .. }
+            | Error::UnknownUnit(_)
+            | Error::NoStart
+            | Error::MissingStation(_)
+            | Error::UnknownStation(_)
+            | Error::UnknownSection(_)
+            | Error::OutsideModel { .. }
+            | Error::Csv { .. } => None,
+            #[cfg(feature = "tml")]
+            Error::Zip(e) => Some(e),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "tml")]
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Self {
+        Error::Zip(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::error::{Error, Result};
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::traverse::{traverse, traverse_with, Traverse};
+use crate::utils::{format_rounded, parse_double};
+
+// WGS84 ellipsoid
+const SEMI_MAJOR_AXIS: f64 = 6378.137;
+const FLATTENING: f64 = 1.0 / 298.257_223_563;
+// Reference radius of the spherical harmonic models
+const REFERENCE_RADIUS: f64 = 6371.2;
+
+/// A spherical harmonic model of the earth's main field, read from the `.COF` coefficient
+/// files the World Magnetic Model is distributed as, or from the coefficient table of the
+/// International Geomagnetic Reference Field. A model can hold several releases, and the
+/// release for the date is used
+#[derive(Debug, Clone)]
+pub struct Model {
+    name: String,
+    // Sorted by epoch
+    releases: Vec<Release>,
+}
+
+// The coefficients of one release, from its epoch
+#[derive(Debug, Clone)]
+struct Release {
+    epoch: f64,
+    degree: usize,
+    // Indexed by n * (n + 1) / 2 + m
+    g: Vec<f64>,
+    h: Vec<f64>,
+    g_rate: Vec<f64>,
+    h_rate: Vec<f64>,
+}
+
+// Releases are published for five years, later dates are extrapolated with growing error
+const RELEASE_YEARS: f64 = 5.0;
+
+fn index(n: usize, m: usize) -> usize {
+    n * (n + 1) / 2 + m
+}
+
+impl Model {
+    /// The releases of the World Magnetic Model embedded in the crate: WMM2020, valid from 2020.0
+    /// to 2025.0. Add later ones with [`Model::extend`]
+    pub fn wmm() -> &'static Model {
+        static MODEL: OnceLock<Model> = OnceLock::new();
+        MODEL.get_or_init(|| Model::from_cof(include_str!("../data/WMM2020.COF")).unwrap())
+    }
+
+    /// Parses a `.COF` file: a header line with the epoch and the model name, then one
+    /// `n m g h g_rate h_rate` line per coefficient, ending with a line of nines
+    pub fn from_cof(text: &str) -> Result<Model> {
+        let mut lines = text.lines();
+        let header = lines.next().unwrap_or_default();
+        let mut fields = header.split_whitespace();
+        let epoch = fields
+            .next()
+            .and_then(|e| e.parse::<f64>().ok())
+            .ok_or_else(|| cof_error(text, header))?;
+        let name = fields.next().unwrap_or_default().to_string();
+
+        let mut rows = Vec::new();
+        for line in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.starts_with("9999") {
+                break;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f64>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .ok()
+                .filter(|v| v.len() == 6 && v[0] >= 1.0 && v[1] <= v[0])
+                .ok_or_else(|| cof_error(text, line))?;
+            rows.push(values);
+        }
+        let degree = rows.iter().map(|v| v[0] as usize).max().unwrap_or(0);
+        let size = index(degree + 1, 0);
+        let mut release = Release {
+            epoch,
+            degree,
+            g: vec![0.0; size],
+            h: vec![0.0; size],
+            g_rate: vec![0.0; size],
+            h_rate: vec![0.0; size],
+        };
+        for v in rows {
+            let i = index(v[0] as usize, v[1] as usize);
+            release.g[i] = v[2];
+            release.h[i] = v[3];
+            release.g_rate[i] = v[4];
+            release.h_rate[i] = v[5];
+        }
+        Ok(Model {
+            name,
+            releases: vec![release],
+        })
+    }
+
+    /// Parses the coefficient table of the IGRF (`igrf14coeffs.txt`): `#` comments, a line of
+    /// column titles, a `g/h n m` line with the epochs and the secular variation column last,
+    /// then one `g` or `h` line per coefficient. Every epoch is a release, changing linearly
+    /// to the next epoch, and the last one changes by the secular variation
+    pub fn from_igrf(text: &str) -> Result<Model> {
+        let mut lines = text
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.trim().is_empty());
+        let mut epochs = Vec::new();
+        for line in lines.by_ref() {
+            let mut fields = line.split_whitespace();
+            if fields.next() == Some("g/h") {
+                epochs = fields
+                    .skip(2)
+                    .map(|e| e.parse::<f64>().ok())
+                    .collect::<Vec<_>>();
+                // The last column is the secular variation, like `2025-30`
+                epochs.pop();
+                if epochs.is_empty() || epochs.contains(&None) {
+                    return Err(cof_error(text, line));
+                }
+                break;
+            }
+        }
+        let epochs: Vec<f64> = epochs.into_iter().flatten().collect();
+        if epochs.is_empty() {
+            return Err(cof_error(text, text.lines().next().unwrap_or_default()));
+        }
+
+        let mut rows = Vec::new();
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let kind = fields.next().unwrap_or_default();
+            let numbers = fields
+                .map(|v| v.parse::<f64>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .ok()
+                .filter(|v| {
+                    matches!(kind, "g" | "h")
+                        && v.len() == epochs.len() + 3
+                        && v[0] >= 1.0
+                        && v[1] <= v[0]
+                })
+                .ok_or_else(|| cof_error(text, line))?;
+            rows.push((kind == "h", numbers));
+        }
+
+        let mut releases = Vec::with_capacity(epochs.len());
+        for (e, &epoch) in epochs.iter().enumerate() {
+            // Older epochs have zeros past degree 10
+            let degree = rows
+                .iter()
+                .filter(|(_, v)| v[2 + e] != 0.0)
+                .map(|(_, v)| v[0] as usize)
+                .max()
+                .unwrap_or(0);
+            let size = index(degree + 1, 0);
+            let mut release = Release {
+                epoch,
+                degree,
+                g: vec![0.0; size],
+                h: vec![0.0; size],
+                g_rate: vec![0.0; size],
+                h_rate: vec![0.0; size],
+            };
+            for (is_h, v) in &rows {
+                let (n, m) = (v[0] as usize, v[1] as usize);
+                if n > degree {
+                    continue;
+                }
+                let rate = match epochs.get(e + 1) {
+                    Some(next) => (v[3 + e] - v[2 + e]) / (next - epoch),
+                    None => v[2 + epochs.len()],
+                };
+                let (value, value_rate) = if *is_h {
+                    (&mut release.h, &mut release.h_rate)
+                } else {
+                    (&mut release.g, &mut release.g_rate)
+                };
+                value[index(n, m)] = v[2 + e];
+                value_rate[index(n, m)] = rate;
+            }
+            releases.push(release);
+        }
+        Ok(Model {
+            name: format!("IGRF {}-{}", epochs[0], epochs[epochs.len() - 1]),
+            releases,
+        })
+    }
+
+    /// Adds the releases of `other`, which replace this model's from their epochs on
+    pub fn extend(&mut self, other: Model) {
+        self.name = format!("{}, {}", self.name, other.name);
+        self.releases.extend(other.releases);
+        self.releases.sort_by(|a, b| a.epoch.total_cmp(&b.epoch));
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The epoch of the first release
+    pub fn valid_from(&self) -> f64 {
+        self.releases.first().map_or(f64::INFINITY, |r| r.epoch)
+    }
+
+    /// Five years after the epoch of the last release
+    pub fn valid_until(&self) -> f64 {
+        self.releases
+            .last()
+            .map_or(f64::NEG_INFINITY, |r| r.epoch + RELEASE_YEARS)
+    }
+
+    // The latest release from before `year`, if `year` is within its five years
+    fn release(&self, year: f64) -> Option<&Release> {
+        self.releases
+            .iter()
+            .rev()
+            .find(|r| r.epoch <= year)
+            .filter(|r| year < r.epoch + RELEASE_YEARS)
+    }
+
+    /// Whether a release of the model is valid in the decimal `year`
+    pub fn covers(&self, year: f64) -> bool {
+        self.release(year).is_some()
+    }
+
+    /// Declination in degrees, positive east, at a geodetic position and decimal year. None if
+    /// no release is valid in `year`
+    pub fn declination(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        height_km: f64,
+        year: f64,
+    ) -> Option<f64> {
+        self.release(year)
+            .map(|release| release.declination(latitude, longitude, height_km, year))
+    }
+}
+
+impl Release {
+    fn declination(&self, latitude: f64, longitude: f64, height_km: f64, year: f64) -> f64 {
+        let latitude = latitude.clamp(-89.999_999, 89.999_999).to_radians();
+        let longitude = longitude.to_radians();
+        let dt = year - self.epoch;
+
+        // Geodetic to geocentric spherical coordinates
+        let e2 = FLATTENING * (2.0 - FLATTENING);
+        let rc = SEMI_MAJOR_AXIS / (1.0 - e2 * latitude.sin().powi(2)).sqrt();
+        let p = (rc + height_km) * latitude.cos();
+        let z = (rc * (1.0 - e2) + height_km) * latitude.sin();
+        let r = p.hypot(z);
+        let phi = (z / r).asin();
+
+        // Schmidt semi-normalized associated Legendre functions and their derivatives
+        // with respect to the geocentric latitude
+        let (x, s) = (phi.sin(), phi.cos());
+        let size = index(self.degree + 1, 0);
+        let mut pnm = vec![0.0; size];
+        let mut dpnm = vec![0.0; size];
+        pnm[0] = 1.0;
+        for n in 1..=self.degree {
+            for m in 0..=n {
+                let i = index(n, m);
+                if m == n {
+                    let k = if n == 1 {
+                        1.0
+                    } else {
+                        ((2 * n - 1) as f64 / (2 * n) as f64).sqrt()
+                    };
+                    let j = index(n - 1, n - 1);
+                    pnm[i] = k * s * pnm[j];
+                    dpnm[i] = k * (s * dpnm[j] - x * pnm[j]);
+                } else {
+                    let j = index(n - 1, m);
+                    let a = (2 * n - 1) as f64;
+                    let b = (((n - 1) * (n - 1) - m * m) as f64).sqrt();
+                    let c = ((n * n - m * m) as f64).sqrt();
+                    let (p2, dp2) = if n >= m + 2 {
+                        let k = index(n - 2, m);
+                        (pnm[k], dpnm[k])
+                    } else {
+                        (0.0, 0.0)
+                    };
+                    pnm[i] = (a * x * pnm[j] - b * p2) / c;
+                    dpnm[i] = (a * (x * dpnm[j] + s * pnm[j]) - b * dp2) / c;
+                }
+            }
+        }
+
+        let (mut north, mut east, mut down) = (0.0, 0.0, 0.0);
+        let ratio = REFERENCE_RADIUS / r;
+        for n in 1..=self.degree {
+            let scale = ratio.powi(n as i32 + 2);
+            for m in 0..=n {
+                let i = index(n, m);
+                let g = self.g[i] + dt * self.g_rate[i];
+                let h = self.h[i] + dt * self.h_rate[i];
+                let (sin, cos) = (m as f64 * longitude).sin_cos();
+                north -= scale * (g * cos + h * sin) * dpnm[i];
+                east += scale * m as f64 * (g * sin - h * cos) * pnm[i];
+                down -= scale * (n + 1) as f64 * (g * cos + h * sin) * pnm[i];
+            }
+        }
+        east /= s;
+        // Rotate the north component back to the geodetic frame
+        let north = north * (phi - latitude).cos() - down * (phi - latitude).sin();
+        east.atan2(north).to_degrees()
+    }
+}
+
+fn cof_error(text: &str, line: &str) -> Error {
+    Error::InvalidValue {
+        position: line.as_ptr() as usize - text.as_ptr() as usize,
+        tag: "COF".to_string(),
+        value: line.to_string(),
+    }
+}
+
+/// Turns a `yyyy-mm-dd` date into a decimal year
+pub fn decimal_year(date: &str) -> Option<f64> {
+    let mut parts = date.trim().splitn(3, '-').map(|p| p.parse::<u32>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_year = if leap { 366.0 } else { 365.0 };
+    let mut days_before =
+        [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334][month as usize - 1];
+    if leap && month > 2 {
+        days_before += 1;
+    }
+    Some(year as f64 + (days_before + day - 1) as f64 / days_in_year)
+}
+
+/// Declination for the date of `leg` at the position of `start`
+pub fn leg_declination(model: &Model, leg: &SurveyData, start: &SurveyData) -> Result<f64> {
+    let year = decimal_year(&leg.date).ok_or_else(|| Error::InvalidField {
+        id: Some(leg.id),
+        tag: "DT".to_string(),
+        value: leg.date.clone(),
+    })?;
+    let latitude = parse_double(&start.latitude, Some(start.id), "LT")?;
+    let longitude = parse_double(&start.longitude, Some(start.id), "LGT")?;
+    model
+        .declination(latitude, longitude, 0.0, year)
+        .ok_or_else(|| Error::OutsideModel {
+            id: leg.id,
+            date: leg.date.clone(),
+        })
+}
+
+pub(crate) fn uses_magnetic_azimuth(cave: &CaveFile) -> bool {
+    cave.info.use_magnetic_azimuth.trim() == "true"
+}
+
+/// Like [`traverse`], but corrects each leg to true north when the file has magnetic azimuths
+pub fn traverse_true_north(cave: &CaveFile, model: &Model) -> Result<Traverse> {
+    if uses_magnetic_azimuth(cave) {
+        traverse_with(cave, |leg, start| leg_declination(model, leg, start))
+    } else {
+        traverse(cave)
+    }
+}
+
+/// Rewrites magnetic azimuths as true ones and sets `useMagneticAzimuth` to false.
+/// Does nothing if the azimuths already are true
+pub fn convert_to_true_north(cave: &mut CaveFile, model: &Model) -> Result<()> {
+    if !uses_magnetic_azimuth(cave) {
+        return Ok(());
+    }
+    rotate_azimuths(cave, model, 1.0)?;
+    cave.info.use_magnetic_azimuth = "false".to_string();
+    Ok(())
+}
+
+/// The reverse of [`convert_to_true_north`]
+pub(crate) fn convert_to_magnetic_north(cave: &mut CaveFile, model: &Model) -> Result<()> {
+    if uses_magnetic_azimuth(cave) {
+        return Ok(());
+    }
+    rotate_azimuths(cave, model, -1.0)?;
+    cave.info.use_magnetic_azimuth = "true".to_string();
+    Ok(())
+}
+
+// Adds the declination of each leg, times `sign`, to its azimuth
+fn rotate_azimuths(cave: &mut CaveFile, model: &Model, sign: f64) -> Result<()> {
+    // The declination of a leg depends on the START it is connected to, stations that are not
+    // connected to one use the first
+    let traverse = traverse(cave)?;
+    let starts: HashMap<i32, &SurveyData> = cave
+        .data
+        .iter()
+        .filter(|s| s.station_type == "START")
+        .map(|s| (s.id, s))
+        .collect();
+    let first = cave.data.iter().find(|s| s.station_type == "START");
+
+    let mut azimuths = Vec::with_capacity(cave.data.len());
+    for srvd in &cave.data {
+        let start = match srvd.station_type.as_str() {
+            "START" | "CLOSURE" => None,
+            _ => traverse
+                .start_of(srvd.id)
+                .and_then(|id| starts.get(&id).copied())
+                .or(first),
+        };
+        let corrected = match start {
+            Some(start) => {
+                let declination = leg_declination(model, srvd, start)?;
+                let azimuth = parse_double(&srvd.azimuth, Some(srvd.id), "AZ")?;
+                Some(format_rounded(
+                    (azimuth + sign * declination).rem_euclid(360.0),
+                ))
+            }
+            None => None,
+        };
+        azimuths.push(corrected);
+    }
+    for (srvd, azimuth) in cave.data.iter_mut().zip(azimuths) {
+        if let Some(azimuth) = azimuth {
+            srvd.azimuth = azimuth;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decimal_year, Model};
+
+    #[test]
+    fn wmm2020_declination() {
+        let model = Model::wmm();
+        let cases = [
+            (2020.0, 0.0, 80.0, 0.0, -1.28),
+            (2020.0, 100.0, 80.0, 0.0, -1.70),
+            (2020.0, 100.0, -80.0, 240.0, 68.78),
+        ];
+        for (year, height, latitude, longitude, expected) in cases {
+            let declination = model
+                .declination(latitude, longitude, height, year)
+                .unwrap();
+            assert!((declination - expected).abs() < 0.01, "{}", declination);
+        }
+    }
+
+    #[test]
+    fn release_for_the_date() {
+        let cof = include_str!("../data/WMM2020.COF");
+        let mut model = Model::from_cof(cof).unwrap();
+        assert_eq!(model.declination(60.0, 10.0, 0.0, 2019.9), None);
+        assert_eq!(model.declination(60.0, 10.0, 0.0, 2025.5), None);
+
+        // The same coefficients again, as a release from 2025
+        model.extend(Model::from_cof(&cof.replacen("2020.0", "2025.0", 1)).unwrap());
+        assert_eq!((model.valid_from(), model.valid_until()), (2020.0, 2030.0));
+        let at = |year| model.declination(60.0, 10.0, 0.0, year).unwrap();
+        assert_eq!(at(2025.5), at(2020.5));
+        assert_ne!(at(2025.5), at(2024.5));
+    }
+
+    // Only the dipole, in the layout of the IGRF table
+    const DIPOLE: &str = "\
+# Not a real model
+c/s deg ord IGRF IGRF SV
+g/h n m 2015.0 2020.0 2020-25
+g 1 0 -29441.5 -29404.8 5.7
+g 1 1 -1501.8 -1450.9 7.4
+h 1 1 4795.99 4652.5 -25.9
+";
+
+    #[test]
+    fn igrf_table() {
+        let model = Model::from_igrf(DIPOLE).unwrap();
+        assert_eq!(model.name(), "IGRF 2015-2020");
+        assert_eq!((model.valid_from(), model.valid_until()), (2015.0, 2025.0));
+        assert_eq!(model.declination(60.0, 10.0, 0.0, 2014.9), None);
+
+        // The same release as a COF file, changing towards 2020
+        let rate = |from: f64, to: f64| (to - from) / 5.0;
+        let cof = format!(
+            "2015.0 DIPOLE\n1 0 -29441.5 0.0 {} 0.0\n1 1 -1501.8 4795.99 {} {}\n999999\n",
+            rate(-29441.5, -29404.8),
+            rate(-1501.8, -1450.9),
+            rate(4795.99, 4652.5)
+        );
+        let cof = Model::from_cof(&cof).unwrap();
+        let at = |model: &Model, year| model.declination(60.0, 10.0, 0.0, year).unwrap();
+        assert!((at(&model, 2019.5) - at(&cof, 2019.5)).abs() < 1e-9);
+        assert!((at(&model, 2019.99) - at(&cof, 2019.99)).abs() < 1e-9);
+        assert_ne!(at(&model, 2024.5), at(&model, 2019.5));
+
+        assert!(Model::from_igrf("g/h n m 2015.0 2015-20\ng 1 0 1.0\n").is_err());
+        assert!(Model::from_igrf("# nothing\n").is_err());
+    }
+
+    #[test]
+    fn decimal_years() {
+        assert_eq!(decimal_year("2020-01-01"), Some(2020.0));
+        assert_eq!(decimal_year("2021-07-02"), Some(2021.0 + 182.0 / 365.0));
+        assert_eq!(decimal_year("2024-13-01"), None);
+        assert_eq!(decimal_year("yesterday"), None);
+    }
+}
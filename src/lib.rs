@@ -1,2 +1,22 @@
+pub mod blunder;
+pub mod builder;
+pub mod csv;
+pub mod diff;
+pub mod elevation;
+pub mod error;
+pub mod geomag;
+pub mod loops;
+pub mod merge;
+pub mod people;
+pub mod projection;
+pub mod renumber;
+pub mod split;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+#[cfg(feature = "tml")]
+pub mod tml;
 pub mod tmlu;
-pub mod utils;
\ No newline at end of file
+pub mod traverse;
+pub mod units;
+pub mod utils;
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::traverse::{Point, Traverse};
+use crate::utils::parse_double;
+
+/// A loop closed by a CLOSURE row. Lengths are in the unit of the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    /// Id of the CLOSURE row
+    pub closure: i32,
+    pub from_id: i32,
+    pub to_id: i32,
+    /// The stations whose legs make up the loop, from `from_id` back to where the paths meet
+    /// and on to `to_id`
+    pub legs: Vec<i32>,
+    pub length: f64,
+    /// Where `to_id` ended up, relative to `from_id`. Zero for a perfect survey
+    pub misclosure: Point,
+}
+
+impl Loop {
+    /// Misclosure relative to the length of the loop
+    pub fn relative_error(&self) -> f64 {
+        if self.length > 0.0 {
+            self.misclosure.length() / self.length
+        } else {
+            0.0
+        }
+    }
+}
+
+// The legs from `id` up to its START station, nearest first
+fn path_to_start(parents: &HashMap<i32, &SurveyData>, id: i32) -> Vec<i32> {
+    let mut path = Vec::new();
+    let mut current = id;
+    while let Some(srvd) = parents.get(&current) {
+        if srvd.station_type == "START" || path.contains(&current) {
+            break;
+        }
+        path.push(current);
+        current = srvd.from_id;
+    }
+    path
+}
+
+/// The loops of the CLOSURE rows, with the positions from `traverse`.
+/// CLOSURE rows with a station that was not reached are left out
+pub fn loops(cave: &CaveFile, traverse: &Traverse) -> Result<Vec<Loop>> {
+    let stations: HashMap<i32, &SurveyData> = cave
+        .data
+        .iter()
+        .filter(|s| s.station_type != "CLOSURE")
+        .map(|s| (s.id, s))
+        .collect();
+    let mut loops = Vec::new();
+    for closure in cave.data.iter().filter(|s| s.station_type == "CLOSURE") {
+        let (from_id, to_id) = (closure.from_id, closure.closure_to_id);
+        let (Some(from), Some(to)) = (traverse.get(from_id), traverse.get(to_id)) else {
+            continue;
+        };
+        let mut from_path = path_to_start(&stations, from_id);
+        let mut to_path = path_to_start(&stations, to_id);
+        // Drop the legs both paths share
+        while let (Some(a), Some(b)) = (from_path.last(), to_path.last()) {
+            if a != b {
+                break;
+            }
+            from_path.pop();
+            to_path.pop();
+        }
+        to_path.reverse();
+        let legs: Vec<i32> = from_path.into_iter().chain(to_path).collect();
+        let mut length = 0.0;
+        for id in &legs {
+            let srvd = stations.get(id).ok_or(Error::MissingStation(*id))?;
+            length += parse_double(&srvd.length, Some(*id), "LG")?;
+        }
+        loops.push(Loop {
+            closure: closure.id,
+            from_id,
+            to_id,
+            legs,
+            length,
+            misclosure: to - from,
+        });
+    }
+    Ok(loops)
+}
@@ -0,0 +1,588 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::{Error, Result};
+use crate::geomag::{
+    convert_to_magnetic_north, convert_to_true_north, uses_magnetic_azimuth, Model,
+};
+use crate::renumber::remap;
+use crate::tmlu::{CaveFile, Shape, SurveyData};
+use crate::units::{convert_units, Unit};
+
+/// Something that did not fit together when merging, and how it was resolved
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// The header values differ, the one from the first file is kept
+    Header {
+        tag: &'static str,
+        kept: String,
+        dropped: String,
+    },
+    /// The second file was converted to the unit of the first
+    UnitConverted { from: String, to: String },
+    /// One of the files had magnetic azimuths, they were converted to true north
+    AzimuthsConverted { file: usize },
+    /// A station name is used in both files
+    DuplicateName { name: String, a: i32, b: i32 },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Header { tag, kept, dropped } => {
+                write!(f, "{}: kept {:?}, dropped {:?}", tag, kept, dropped)
+            }
+            Conflict::UnitConverted { from, to } => {
+                write!(f, "converted the second file from {} to {}", from, to)
+            }
+            Conflict::AzimuthsConverted { file } => write!(
+                f,
+                "converted the azimuths of the {} file to true north",
+                if *file == 0 { "first" } else { "second" }
+            ),
+            Conflict::DuplicateName { name, a, b } => write!(
+                f,
+                "station {} of the first file and {} of the second are both named {:?}",
+                a, b, name
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Merge {
+    pub cave: CaveFile,
+    /// New ids of the stations of the second file
+    pub ids: HashMap<i32, i32>,
+    pub conflicts: Vec<Conflict>,
+}
+
+// Names Ariane gives stations by default
+fn is_default_name(name: &str) -> bool {
+    matches!(name, "" | "START" | "CLOSURE:CLOSURE")
+}
+
+/// Appends the stations of `b` to `a`, moving the ids of `b` past the ones in `a`.
+/// `tie` adds a CLOSURE row joining a station in `a` with a station in `b` (by their ids in `b`).
+/// `b` is converted to the unit of `a`, and if only one file uses magnetic azimuths it is
+/// converted to true north with `model`
+pub fn merge(
+    mut a: CaveFile,
+    mut b: CaveFile,
+    tie: Option<(i32, i32)>,
+    model: &Model,
+) -> Result<Merge> {
+    if let Some((in_a, in_b)) = tie {
+        for (cave, id) in [(&a, in_a), (&b, in_b)] {
+            if !cave.data.iter().any(|s| s.id == id) {
+                return Err(Error::MissingStation(id));
+            }
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    let unit: Unit = a.info.unit.parse()?;
+    if b.info.unit.parse::<Unit>()? != unit {
+        conflicts.push(Conflict::UnitConverted {
+            from: b.info.unit.clone(),
+            to: a.info.unit.clone(),
+        });
+        convert_units(&mut b, unit)?;
+    }
+    if uses_magnetic_azimuth(&a) != uses_magnetic_azimuth(&b) {
+        let (file, cave) = if uses_magnetic_azimuth(&a) {
+            (0, &mut a)
+        } else {
+            (1, &mut b)
+        };
+        convert_to_true_north(cave, model)?;
+        conflicts.push(Conflict::AzimuthsConverted { file });
+    }
+
+    let headers = [
+        ("caveName", &mut a.info.cave_name, &b.info.cave_name),
+        (
+            "firstStartAbsoluteElevation",
+            &mut a.info.first_start_absolute_elevation,
+            &b.info.first_start_absolute_elevation,
+        ),
+    ];
+    for (tag, kept, other) in headers {
+        if kept.is_empty() {
+            kept.clone_from(other);
+        } else if !other.is_empty() && kept != other {
+            conflicts.push(Conflict::Header {
+                tag,
+                kept: kept.clone(),
+                dropped: other.clone(),
+            });
+        }
+    }
+    match (&a.info.geo_coding, b.info.geo_coding) {
+        (None, other) => a.info.geo_coding = other,
+        (Some(kept), Some(other)) if *kept != other => conflicts.push(Conflict::Header {
+            tag: "geoCoding",
+            kept: kept.clone(),
+            dropped: other,
+        }),
+        _ => (),
+    }
+    for annotation in b.info.annotations {
+        if !a.info.annotations.contains(&annotation) {
+            a.info.annotations.push(annotation);
+        }
+    }
+    for layer in b.info.layers {
+        if !a.info.layers.iter().any(|l| l.name == layer.name) {
+            a.info.layers.push(layer);
+        }
+    }
+    for overlay in b.info.carto_overlay {
+        if !a
+            .info
+            .carto_overlay
+            .iter()
+            .any(|o| o.file_name == overlay.file_name)
+        {
+            a.info.carto_overlay.push(overlay);
+        }
+    }
+    for surface in b.info.carto_linked_surface {
+        if !a
+            .info
+            .carto_linked_surface
+            .iter()
+            .any(|s| s.file_name == surface.file_name)
+        {
+            a.info.carto_linked_surface.push(surface);
+        }
+    }
+    for unknown in b.info.unknown {
+        if !a.info.unknown.contains(&unknown) {
+            a.info.unknown.push(unknown);
+        }
+    }
+
+    let names: HashMap<&str, i32> = a
+        .data
+        .iter()
+        .filter_map(|s| Some((s.name.as_deref()?, s.id)))
+        .filter(|(name, _)| !is_default_name(name))
+        .collect();
+    for srvd in &b.data {
+        if let Some((name, &id)) = srvd
+            .name
+            .as_deref()
+            .and_then(|name| names.get_key_value(name))
+        {
+            conflicts.push(Conflict::DuplicateName {
+                name: name.to_string(),
+                a: id,
+                b: srvd.id,
+            });
+        }
+    }
+
+    let next = a.data.iter().map(|s| s.id + 1).max().unwrap_or(0);
+    let offset = b
+        .data
+        .iter()
+        .map(|s| s.id)
+        .min()
+        .map_or(0, |first| next - first);
+    let ids: HashMap<i32, i32> = b.data.iter().map(|s| (s.id, s.id + offset)).collect();
+    let tie_date =
+        tie.and_then(|(_, in_b)| b.data.iter().find(|s| s.id == in_b).map(|s| s.date.clone()));
+    for mut srvd in b.data {
+        remap(&mut srvd, |id| (id >= 0).then_some(id + offset));
+        a.data.push(srvd);
+    }
+    if let Some((in_a, in_b)) = tie {
+        let id = a.data.iter().map(|s| s.id + 1).max().unwrap_or(0);
+        let date = tie_date.unwrap_or_default();
+        a.data
+            .push(SurveyData::closure(id, in_a, ids[&in_b], &date));
+    }
+
+    Ok(Merge {
+        cave: a,
+        ids,
+        conflicts,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// A change both sides made differently. The merged file has the value from ours
+#[derive(Debug, Clone, PartialEq)]
+pub enum Merge3Conflict {
+    Header {
+        tag: &'static str,
+        base: String,
+        ours: String,
+        theirs: String,
+    },
+    Field {
+        id: i32,
+        tag: &'static str,
+        base: String,
+        ours: String,
+        theirs: String,
+    },
+    /// One side removed a station the other side changed, the changed station is kept
+    DeleteModify { id: i32, deleted_by: Side },
+}
+
+impl fmt::Display for Merge3Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Merge3Conflict::Header {
+                tag,
+                base,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "{}: ours {:?}, theirs {:?} (was {:?})",
+                tag, ours, theirs, base
+            ),
+            Merge3Conflict::Field {
+                id,
+                tag,
+                base,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "station {} {}: ours {:?}, theirs {:?} (was {:?})",
+                id, tag, ours, theirs, base
+            ),
+            Merge3Conflict::DeleteModify { id, deleted_by } => {
+                let (deleted, changed) = match deleted_by {
+                    Side::Ours => ("ours", "theirs"),
+                    Side::Theirs => ("theirs", "ours"),
+                };
+                write!(
+                    f,
+                    "station {}: removed by {}, changed by {}",
+                    id, deleted, changed
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Merge3 {
+    pub cave: CaveFile,
+    /// Stations added by theirs that got a new id because ours added a different one with the
+    /// same id
+    pub renumbered: HashMap<i32, i32>,
+    pub conflicts: Vec<Merge3Conflict>,
+}
+
+impl Merge3 {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+// How a conflicting value is shown
+trait Describe {
+    fn describe(&self) -> String;
+}
+
+impl Describe for String {
+    fn describe(&self) -> String {
+        self.clone()
+    }
+}
+
+impl Describe for i32 {
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<T: Describe> Describe for Option<T> {
+    fn describe(&self) -> String {
+        self.as_ref().map(T::describe).unwrap_or_default()
+    }
+}
+
+impl<T: fmt::Debug> Describe for Vec<T> {
+    fn describe(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl Describe for Shape {
+    fn describe(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+// Takes the side that changed a field, and records a conflict when both did
+macro_rules! merge_fields {
+    ($base:expr, $ours:expr, $theirs:expr, $merged:expr, $conflict:expr; $($field:ident: $tag:literal),* $(,)?) => {
+        $(
+            if $ours.$field != $theirs.$field {
+                if $ours.$field == $base.$field {
+                    $merged.$field = $theirs.$field.clone();
+                } else if $theirs.$field != $base.$field {
+                    $conflict(
+                        $tag,
+                        $base.$field.describe(),
+                        $ours.$field.describe(),
+                        $theirs.$field.describe(),
+                    );
+                }
+            }
+        )*
+    };
+}
+
+fn merge_station(
+    base: &SurveyData,
+    ours: &SurveyData,
+    theirs: &SurveyData,
+    conflicts: &mut Vec<Merge3Conflict>,
+) -> SurveyData {
+    let mut merged = ours.clone();
+    let id = ours.id;
+    let mut conflict = |tag, base, ours, theirs| {
+        conflicts.push(Merge3Conflict::Field {
+            id,
+            tag,
+            base,
+            ours,
+            theirs,
+        })
+    };
+    merge_fields!(base, ours, theirs, merged, conflict;
+        azimuth: "AZ", closure_to_id: "CID", color: "CL", comment: "CM", date: "DT",
+        depth: "DP", depth_in: "DPI", down: "D", excluded: "EXC", explorer: "EX",
+        from_id: "FRID", inclination: "INC", latitude: "LT", left: "L", length: "LG",
+        locked: "LK", longitude: "LGT", name: "NM", profile_type: "PRTY", right: "R",
+        section: "SC", shape: "SH", station_type: "TY", up: "U", unknown: "unknown",
+    );
+    merged
+}
+
+// `cave` in `unit`, and with true azimuths if `true_north`
+fn normalised<'a>(
+    cave: &'a CaveFile,
+    unit: Unit,
+    true_north: bool,
+    model: &Model,
+) -> Result<Cow<'a, CaveFile>> {
+    let mut cave = Cow::Borrowed(cave);
+    if cave.info.unit.parse::<Unit>()? != unit {
+        convert_units(cave.to_mut(), unit)?;
+    }
+    if true_north && uses_magnetic_azimuth(&cave) {
+        convert_to_true_north(cave.to_mut(), model)?;
+    }
+    Ok(cave)
+}
+
+// Values that only differ from base by the rounding of a conversion
+const ROUNDING: f64 = 1e-5;
+
+// Puts back the values of `base` where `cave` has them converted, but not changed
+fn unconverted(cave: &mut CaveFile, base: &CaveFile) {
+    let same = |a: &str, b: &str| match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => (a - b).abs() <= ROUNDING,
+        _ => false,
+    };
+    let restore = |value: &mut String, original: &String| {
+        if same(value, original) {
+            value.clone_from(original);
+        }
+    };
+    restore(
+        &mut cave.info.first_start_absolute_elevation,
+        &base.info.first_start_absolute_elevation,
+    );
+    let in_base: HashMap<i32, &SurveyData> = base.data.iter().map(|s| (s.id, s)).collect();
+    for srvd in &mut cave.data {
+        let Some(original) = in_base.get(&srvd.id) else {
+            continue;
+        };
+        for (value, original) in [
+            (&mut srvd.azimuth, &original.azimuth),
+            (&mut srvd.length, &original.length),
+            (&mut srvd.depth, &original.depth),
+            (&mut srvd.depth_in, &original.depth_in),
+            (&mut srvd.left, &original.left),
+            (&mut srvd.right, &original.right),
+            (&mut srvd.up, &original.up),
+            (&mut srvd.down, &original.down),
+        ] {
+            restore(value, original);
+        }
+        let radii = &original.shape.radius_collection;
+        if srvd.shape.radius_collection.len() == radii.len() {
+            for (rv, original) in srvd.shape.radius_collection.iter_mut().zip(radii) {
+                restore(&mut rv.length, &original.length);
+            }
+        }
+    }
+}
+
+/// Three-way merge of two versions of `base`, station by station.
+/// Changes made on one side are taken, fields changed differently on both sides are
+/// conflicts. Stations both sides added with the same id are kept once if they are equal,
+/// otherwise the one from theirs is given a new id.
+/// The unit and azimuth reference are merged like the other header fields. The stations are
+/// compared in the unit of base, and if only some of the files use magnetic azimuths, with
+/// those converted to true north with `model`. Values a conversion only rounded count as
+/// unchanged. The result is converted to the merged unit and azimuth reference
+pub fn merge3(
+    base: &CaveFile,
+    ours: &CaveFile,
+    theirs: &CaveFile,
+    model: &Model,
+) -> Result<Merge3> {
+    let mut conflicts = Vec::new();
+    let mut header = ours.info.clone();
+    {
+        let mut conflict = |tag, base, ours, theirs| {
+            conflicts.push(Merge3Conflict::Header {
+                tag,
+                base,
+                ours,
+                theirs,
+            })
+        };
+        merge_fields!(base.info, ours.info, theirs.info, header, conflict;
+            unit: "unit", use_magnetic_azimuth: "useMagneticAzimuth",
+        );
+    }
+    let unit: Unit = base.info.unit.parse()?;
+    let magnetic = [base, ours, theirs].map(uses_magnetic_azimuth);
+    let true_north = magnetic.contains(&true) && magnetic.contains(&false);
+    let base = normalised(base, unit, true_north, model)?;
+    let mut ours = normalised(ours, unit, true_north, model)?;
+    let mut theirs = normalised(theirs, unit, true_north, model)?;
+    for side in [&mut ours, &mut theirs] {
+        if let Cow::Owned(cave) = side {
+            unconverted(cave, &base);
+        }
+    }
+    let (base, ours, theirs) = (base.as_ref(), ours.as_ref(), theirs.as_ref());
+
+    let mut info = ours.info.clone();
+    {
+        let mut conflict = |tag, base, ours, theirs| {
+            conflicts.push(Merge3Conflict::Header {
+                tag,
+                base,
+                ours,
+                theirs,
+            })
+        };
+        merge_fields!(base.info, ours.info, theirs.info, info, conflict;
+            cave_name: "caveName",
+            first_start_absolute_elevation: "firstStartAbsoluteElevation",
+            geo_coding: "geoCoding", annotations: "ListAnnotation", layers: "Layers",
+            carto_overlay: "CartoOverlay", carto_linked_surface: "CartoLinkedSurface",
+            unknown: "unknown",
+        );
+    }
+
+    let index = |cave: &CaveFile| -> HashMap<i32, usize> {
+        cave.data
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id, i))
+            .collect()
+    };
+    let (in_base, in_ours) = (index(base), index(ours));
+
+    // Stations added on both sides with the same id, but different content
+    let mut next = [base, ours, theirs]
+        .iter()
+        .flat_map(|c| c.data.iter().map(|s| s.id + 1))
+        .max()
+        .unwrap_or(0);
+    let mut renumbered = HashMap::new();
+    for srvd in &theirs.data {
+        if in_base.contains_key(&srvd.id) {
+            continue;
+        }
+        if let Some(&i) = in_ours.get(&srvd.id) {
+            if ours.data[i] != *srvd {
+                renumbered.insert(srvd.id, next);
+                next += 1;
+            }
+        }
+    }
+    let mut theirs_data = theirs.data.clone();
+    for srvd in &mut theirs_data {
+        remap(srvd, |id| renumbered.get(&id).copied());
+    }
+    let in_theirs: HashMap<i32, usize> = theirs_data
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.id, i))
+        .collect();
+
+    let mut data = Vec::with_capacity(ours.data.len());
+    for srvd in &ours.data {
+        match (in_base.get(&srvd.id), in_theirs.get(&srvd.id)) {
+            (Some(&b), Some(&t)) => data.push(merge_station(
+                &base.data[b],
+                srvd,
+                &theirs_data[t],
+                &mut conflicts,
+            )),
+            (Some(&b), None) => {
+                if base.data[b] != *srvd {
+                    conflicts.push(Merge3Conflict::DeleteModify {
+                        id: srvd.id,
+                        deleted_by: Side::Theirs,
+                    });
+                    data.push(srvd.clone());
+                }
+            }
+            // Added by ours, or by both with the same content
+            (None, _) => data.push(srvd.clone()),
+        }
+    }
+    for srvd in &theirs_data {
+        if in_ours.contains_key(&srvd.id) {
+            continue;
+        }
+        match in_base.get(&srvd.id) {
+            Some(&b) => {
+                if base.data[b] != *srvd {
+                    conflicts.push(Merge3Conflict::DeleteModify {
+                        id: srvd.id,
+                        deleted_by: Side::Ours,
+                    });
+                    data.push(srvd.clone());
+                }
+            }
+            None => data.push(srvd.clone()),
+        }
+    }
+
+    let mut cave = CaveFile { info, data };
+    convert_units(&mut cave, header.unit.parse()?)?;
+    if true_north && header.use_magnetic_azimuth.trim() == "true" {
+        convert_to_magnetic_north(&mut cave, model)?;
+    }
+    cave.info.unit = header.unit;
+    cave.info.use_magnetic_azimuth = header.use_magnetic_azimuth;
+    Ok(Merge3 {
+        cave,
+        renumbered,
+        conflicts,
+    })
+}
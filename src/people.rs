@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use crate::error::Result;
+use crate::tmlu::CaveFile;
+use crate::units::Unit;
+use crate::utils::{clean_name, parse_double};
+
+/// What one person did in a survey. Lengths and depths are in meters
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct Person {
+    /// The name as it was first written, with the spacing cleaned up
+    pub name: String,
+    /// Legs with this person as a surveyor
+    pub legs_surveyed: usize,
+    /// Legs with this person as an explorer
+    pub legs_explored: usize,
+    /// Length of the legs with this person as an explorer
+    pub meters_explored: f64,
+    pub first_date: Option<String>,
+    pub last_date: Option<String>,
+    /// The deepest station this person explored or surveyed, and its depth
+    pub deepest: Option<(i32, f64)>,
+}
+
+/// Names that only differ in spacing or case are the same person
+pub fn same_person(a: &str, b: &str) -> bool {
+    clean_name(a).to_lowercase() == clean_name(b).to_lowercase()
+}
+
+// The person with `name`, updated with the date and depth of a leg they were on
+fn record<'a>(
+    people: &'a mut BTreeMap<String, Person>,
+    name: &str,
+    date: &str,
+    (id, depth): (i32, f64),
+) -> &'a mut Person {
+    let person = people.entry(name.to_lowercase()).or_insert_with(|| Person {
+        name: name.to_string(),
+        ..Default::default()
+    });
+    if !date.is_empty() {
+        if person
+            .first_date
+            .as_deref()
+            .is_none_or(|first| date < first)
+        {
+            person.first_date = Some(date.to_string());
+        }
+        if person.last_date.as_deref().is_none_or(|last| date > last) {
+            person.last_date = Some(date.to_string());
+        }
+    }
+    if person.deepest.is_none_or(|(_, deepest)| depth > deepest) {
+        person.deepest = Some((id, depth));
+    }
+    person
+}
+
+/// Totals per explorer and surveyor from the `EX` field of the REAL legs that are not
+/// excluded, ordered by name
+pub fn people(cave: &CaveFile) -> Result<Vec<Person>> {
+    let meters = cave.info.unit.parse::<Unit>()?.factor(Unit::Meters);
+    let mut people: BTreeMap<String, Person> = BTreeMap::new();
+    for srvd in &cave.data {
+        if srvd.station_type != "REAL" || srvd.excluded.trim() == "true" {
+            continue;
+        }
+        let team = srvd.team();
+        if team.is_empty() {
+            continue;
+        }
+        let id = Some(srvd.id);
+        let length = parse_double(&srvd.length, id, "LG")? * meters;
+        let depth = parse_double(&srvd.depth, id, "DP")? * meters;
+        let date = srvd.date.trim();
+
+        // A name listed twice on a leg counts once
+        let once = |mut names: Vec<String>| {
+            names.sort_by_key(|n| n.to_lowercase());
+            names.dedup_by(|a, b| same_person(a, b));
+            names
+        };
+        for name in once(team.explorers) {
+            let person = record(&mut people, &name, date, (srvd.id, depth));
+            person.legs_explored += 1;
+            person.meters_explored += length;
+        }
+        for name in once(team.surveyors) {
+            record(&mut people, &name, date, (srvd.id, depth)).legs_surveyed += 1;
+        }
+    }
+    Ok(people.into_values().collect())
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::geomag::{leg_declination, Model};
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::traverse::traverse_with;
+use crate::units::Unit;
+use crate::utils::parse_double;
+
+// WGS84 ellipsoid
+const SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+const FLATTENING: f64 = 1.0 / 298.257_223_563;
+
+/// A transverse Mercator projection on the WGS84 ellipsoid, computed with the Krüger series
+/// which is accurate to well below a millimeter within the width of a UTM zone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransverseMercator {
+    /// Longitude of the central meridian in degrees
+    pub central_meridian: f64,
+    /// Latitude the northing is counted from, in degrees
+    pub latitude_of_origin: f64,
+    pub scale_factor: f64,
+    pub false_easting: f64,
+    pub false_northing: f64,
+}
+
+/// Position of a station in a projected coordinate system, all in meters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectedStation {
+    pub id: i32,
+    pub easting: f64,
+    pub northing: f64,
+    pub elevation: f64,
+}
+
+impl TransverseMercator {
+    pub fn new(central_meridian: f64) -> TransverseMercator {
+        TransverseMercator {
+            central_meridian,
+            latitude_of_origin: 0.0,
+            scale_factor: 1.0,
+            false_easting: 0.0,
+            false_northing: 0.0,
+        }
+    }
+
+    /// UTM zone 1 to 60, on the northern or southern hemisphere
+    pub fn utm(zone: u8, north: bool) -> TransverseMercator {
+        TransverseMercator {
+            central_meridian: zone as f64 * 6.0 - 183.0,
+            latitude_of_origin: 0.0,
+            scale_factor: 0.9996,
+            false_easting: 500_000.0,
+            false_northing: if north { 0.0 } else { 10_000_000.0 },
+        }
+    }
+
+    /// The UTM projection for the zone a position is in, including the exceptions around
+    /// Norway and Svalbard
+    pub fn utm_for(latitude: f64, longitude: f64) -> TransverseMercator {
+        let longitude = (longitude + 180.0).rem_euclid(360.0) - 180.0;
+        let mut zone = ((longitude + 180.0) / 6.0).floor() as u8 + 1;
+        if (56.0..64.0).contains(&latitude) && (3.0..12.0).contains(&longitude) {
+            zone = 32;
+        } else if (72.0..84.0).contains(&latitude) && (0.0..42.0).contains(&longitude) {
+            zone = match longitude {
+                l if l < 9.0 => 31,
+                l if l < 21.0 => 33,
+                l if l < 33.0 => 35,
+                _ => 37,
+            };
+        }
+        TransverseMercator::utm(zone.min(60), latitude >= 0.0)
+    }
+
+    /// The UTM zone number, if this is a UTM projection
+    pub fn utm_zone(&self) -> Option<u8> {
+        let zone = (self.central_meridian + 183.0) / 6.0;
+        let is_utm = self.scale_factor == 0.9996
+            && self.false_easting == 500_000.0
+            && self.latitude_of_origin == 0.0
+            && zone.fract() == 0.0
+            && (1.0..=60.0).contains(&zone);
+        is_utm.then_some(zone as u8)
+    }
+
+    /// Projects a position and returns `(easting, northing, convergence, scale)`, where the grid
+    /// convergence is the angle in degrees from true north to grid north, positive east
+    pub fn project(&self, latitude: f64, longitude: f64) -> (f64, f64, f64, f64) {
+        let (xi, eta, convergence, scale) = self.krueger(latitude, longitude);
+        let (xi0, ..) = self.krueger(self.latitude_of_origin, self.central_meridian);
+        (
+            self.false_easting + self.scale_factor * eta,
+            self.false_northing + self.scale_factor * (xi - xi0),
+            convergence,
+            scale,
+        )
+    }
+
+    // Returns the unscaled northing and easting, the convergence and the point scale factor
+    fn krueger(&self, latitude: f64, longitude: f64) -> (f64, f64, f64, f64) {
+        let n = FLATTENING / (2.0 - FLATTENING);
+        let (n2, n3, n4) = (n * n, n * n * n, n * n * n * n);
+        let rectifying_radius = SEMI_MAJOR_AXIS / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0);
+        let alpha = [
+            n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0,
+            13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0,
+            61.0 * n3 / 240.0 - 103.0 * n4 / 140.0,
+            49561.0 * n4 / 161280.0,
+        ];
+
+        let phi = latitude.to_radians();
+        let lambda = (longitude - self.central_meridian).to_radians();
+        let e = 2.0 * n.sqrt() / (1.0 + n);
+        let t = (phi.sin().atanh() - e * (e * phi.sin()).atanh()).sinh();
+        let xi_prime = t.atan2(lambda.cos());
+        let eta_prime = (lambda.sin() / (1.0 + t * t).sqrt()).atanh();
+
+        let (mut xi, mut eta) = (xi_prime, eta_prime);
+        let (mut sigma, mut tau) = (1.0, 0.0);
+        for (j, a) in alpha.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            xi += a * (k * xi_prime).sin() * (k * eta_prime).cosh();
+            eta += a * (k * xi_prime).cos() * (k * eta_prime).sinh();
+            sigma += k * a * (k * xi_prime).cos() * (k * eta_prime).cosh();
+            tau += k * a * (k * xi_prime).sin() * (k * eta_prime).sinh();
+        }
+
+        let tt = xi_prime.tan() * eta_prime.tanh();
+        let convergence = (tau + sigma * tt).atan2(sigma - tau * tt).to_degrees();
+        let tan_phi = (1.0 - n) / (1.0 + n) * phi.tan();
+        let scale = self.scale_factor * rectifying_radius / SEMI_MAJOR_AXIS
+            * ((1.0 + tan_phi * tan_phi) * (sigma * sigma + tau * tau)
+                / (t * t + lambda.cos() * lambda.cos()))
+            .sqrt();
+        (
+            rectifying_radius * xi,
+            rectifying_radius * eta,
+            convergence,
+            scale,
+        )
+    }
+}
+
+fn start_position(start: &SurveyData) -> Result<(f64, f64)> {
+    Ok((
+        parse_double(&start.latitude, Some(start.id), "LT")?,
+        parse_double(&start.longitude, Some(start.id), "LGT")?,
+    ))
+}
+
+/// Projects every station that is connected to a START station.
+/// Each START is projected from its latitude and longitude, the legs from it are turned from
+/// true to grid north and scaled by the point scale factor at the START. Magnetic azimuths are
+/// corrected with `model` first. The elevation counts from `firstStartAbsoluteElevation` at
+/// depth 0
+pub fn project(
+    cave: &CaveFile,
+    projection: &TransverseMercator,
+    model: &Model,
+) -> Result<Vec<ProjectedStation>> {
+    let unit: Unit = cave.info.unit.parse()?;
+    let meters = unit.factor(Unit::Meters);
+    let datum = parse_double(
+        &cave.info.first_start_absolute_elevation,
+        None,
+        "firstStartAbsoluteElevation",
+    )? * meters;
+    let magnetic = cave.info.use_magnetic_azimuth.trim() == "true";
+
+    let mut starts: HashMap<i32, (f64, f64, f64, f64)> = HashMap::new();
+    for start in cave.data.iter().filter(|s| s.station_type == "START") {
+        let (latitude, longitude) = start_position(start)?;
+        starts.insert(start.id, projection.project(latitude, longitude));
+    }
+    let traverse = traverse_with(cave, |leg, start| {
+        let convergence = starts[&start.id].2;
+        let declination = if magnetic {
+            leg_declination(model, leg, start)?
+        } else {
+            0.0
+        };
+        Ok(declination - convergence)
+    })?;
+
+    let mut result = Vec::with_capacity(traverse.len());
+    for (id, point) in traverse.iter() {
+        let start = traverse.start_of(id).unwrap();
+        let origin = traverse.get(start).unwrap();
+        let (easting, northing, _, scale) = starts[&start];
+        let offset = point - origin;
+        result.push(ProjectedStation {
+            id,
+            easting: easting + offset.x * meters * scale,
+            northing: northing + offset.y * meters * scale,
+            elevation: datum + point.z * meters,
+        });
+    }
+    Ok(result)
+}
+
+/// Projects the stations to the UTM zone of the first START station
+pub fn project_utm(
+    cave: &CaveFile,
+    model: &Model,
+) -> Result<(TransverseMercator, Vec<ProjectedStation>)> {
+    let start = cave
+        .data
+        .iter()
+        .find(|s| s.station_type == "START")
+        .ok_or(Error::NoStart)?;
+    let (latitude, longitude) = start_position(start)?;
+    let projection = TransverseMercator::utm_for(latitude, longitude);
+    let stations = project(cave, &projection, model)?;
+    Ok((projection, stations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransverseMercator;
+
+    #[test]
+    fn utm_projection() {
+        let utm = TransverseMercator::utm(31, true);
+        let (easting, northing, convergence, scale) = utm.project(0.0, 3.0);
+        assert!((easting - 500_000.0).abs() < 1e-6 && northing.abs() < 1e-6);
+        assert!(convergence.abs() < 1e-9 && (scale - 0.9996).abs() < 1e-12);
+
+        // Length of the meridian from the equator to 45 degrees
+        let (_, northing, ..) = utm.project(45.0, 3.0);
+        assert!((northing - 0.9996 * 4_984_944.378).abs() < 0.01);
+
+        let (east, ..) = utm.project(10.0, 5.0);
+        let (west, ..) = utm.project(10.0, 1.0);
+        assert!((east - 500_000.0 - (500_000.0 - west)).abs() < 1e-6);
+
+        let (.., convergence, _) = TransverseMercator::utm(41, true).project(60.0, 60.0);
+        let expected = -(3f64.to_radians().tan() * 60f64.to_radians().sin()).atan();
+        assert!((convergence - expected.to_degrees()).abs() < 0.01);
+    }
+
+    #[test]
+    fn utm_zones() {
+        assert_eq!(TransverseMercator::utm_for(60.0, 60.0).utm_zone(), Some(41));
+        assert_eq!(TransverseMercator::utm_for(60.0, 5.0).utm_zone(), Some(32));
+        assert_eq!(TransverseMercator::utm_for(78.0, 15.0).utm_zone(), Some(33));
+        assert_eq!(
+            TransverseMercator::utm_for(-33.9, 18.4).false_northing,
+            10_000_000.0
+        );
+        assert_eq!(TransverseMercator::new(15.0).utm_zone(), None);
+    }
+}
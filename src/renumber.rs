@@ -0,0 +1,88 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::tmlu::{CaveFile, SurveyData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// The order of the stations in the file
+    File,
+    /// Depth first from each START station, branches in file order. Stations that can not be
+    /// reached from a START come last
+    Traversal,
+    /// Ascending by the old id
+    Sorted,
+}
+
+/// Changes the ids of `srvd` with `map`, ids outside the map are left alone
+pub(crate) fn remap(srvd: &mut SurveyData, map: impl Fn(i32) -> Option<i32>) {
+    for id in [&mut srvd.id, &mut srvd.from_id, &mut srvd.closure_to_id] {
+        if let Some(new) = map(*id) {
+            *id = new;
+        }
+    }
+}
+
+fn traversal_order(data: &[SurveyData]) -> Vec<usize> {
+    let mut children: HashMap<i32, Vec<usize>> = HashMap::new();
+    for (i, srvd) in data.iter().enumerate() {
+        if srvd.station_type != "START" {
+            children.entry(srvd.from_id).or_default().push(i);
+        }
+    }
+    let mut order = Vec::with_capacity(data.len());
+    let mut seen = HashSet::new();
+    let starts = data
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.station_type == "START");
+    for (start, _) in starts {
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            if !seen.insert(i) {
+                continue;
+            }
+            order.push(i);
+            if data[i].station_type != "CLOSURE" {
+                let next = children.get(&data[i].id).into_iter().flatten().rev();
+                stack.extend(next);
+            }
+        }
+    }
+    order.extend((0..data.len()).filter(|i| !seen.contains(i)));
+    order
+}
+
+/// Gives the stations the ids 0, 1, 2... in `order`, sorts them by the new ids and rewrites
+/// `from_id` and `closure_to_id`. Returns the new id of each old one
+pub fn renumber(cave: &mut CaveFile, order: Order) -> HashMap<i32, i32> {
+    let indices: Vec<usize> = match order {
+        Order::File => (0..cave.data.len()).collect(),
+        Order::Traversal => traversal_order(&cave.data),
+        Order::Sorted => {
+            let mut indices: Vec<usize> = (0..cave.data.len()).collect();
+            indices.sort_by_key(|&i| cave.data[i].id);
+            indices
+        }
+    };
+
+    let mut ids = HashMap::with_capacity(indices.len());
+    for (new, &i) in indices.iter().enumerate() {
+        ids.entry(cave.data[i].id).or_insert(new as i32);
+    }
+    let mut old: Vec<Option<SurveyData>> = std::mem::take(&mut cave.data)
+        .into_iter()
+        .map(Some)
+        .collect();
+    cave.data = indices
+        .iter()
+        .enumerate()
+        .map(|(new, &i)| {
+            let mut srvd = old[i].take().unwrap();
+            remap(&mut srvd, |id| ids.get(&id).copied());
+            // A duplicated id still gets an id of its own
+            srvd.id = new as i32;
+            srvd
+        })
+        .collect();
+    ids
+}
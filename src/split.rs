@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{Error, Result};
+use crate::geomag::{traverse_true_north, Model};
+use crate::renumber::{renumber, Order};
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::utils::{format_double, section_name};
+
+#[derive(Debug, Clone)]
+pub struct Split {
+    pub cave: CaveFile,
+    /// New ids of the stations that were kept, by their old id
+    pub ids: HashMap<i32, i32>,
+}
+
+/// Extracts the stations of a section into a file of their own.
+/// Sections are matched by name, without the description Ariane appends to it
+pub fn split_section(cave: &CaveFile, section: &str, model: &Model) -> Result<Split> {
+    let keep: HashSet<i32> = cave
+        .data
+        .iter()
+        .filter(|s| s.station_type != "CLOSURE")
+        .filter(|s| s.section.as_deref().map(section_name) == Some(section_name(section)))
+        .map(|s| s.id)
+        .collect();
+    if keep.is_empty() {
+        return Err(Error::UnknownSection(section.to_string()));
+    }
+    extract(cave, keep, model)
+}
+
+/// Extracts `root` and every station surveyed from it into a file of their own
+pub fn split_subtree(cave: &CaveFile, root: i32, model: &Model) -> Result<Split> {
+    if !cave.data.iter().any(|s| s.id == root) {
+        return Err(Error::MissingStation(root));
+    }
+    let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+    for srvd in cave.data.iter().filter(|s| s.station_type != "CLOSURE") {
+        children.entry(srvd.from_id).or_default().push(srvd.id);
+    }
+    let mut keep = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(id) = stack.pop() {
+        if keep.insert(id) {
+            stack.extend(children.get(&id).into_iter().flatten());
+        }
+    }
+    extract(cave, keep, model)
+}
+
+/// Builds a file from the stations in `keep`.
+/// When a station is surveyed from one that is not kept, that one is added as a START
+/// station at its computed position, so the leg to it is not lost. CLOSURE rows are kept when
+/// both their stations are. The ids are renumbered from 0 in file order, cut points first.
+/// The positions of the cut points are worked out with `model` if the azimuths are magnetic
+pub fn extract(cave: &CaveFile, keep: HashSet<i32>, model: &Model) -> Result<Split> {
+    let traverse = traverse_true_north(cave, model)?;
+    let by_id: HashMap<i32, &SurveyData> = cave.data.iter().map(|s| (s.id, s)).collect();
+
+    let mut cuts = Vec::new();
+    let mut cut_ids = HashSet::new();
+    for srvd in cave.data.iter().filter(|s| keep.contains(&s.id)) {
+        let parent = srvd.from_id;
+        if srvd.station_type == "START" || keep.contains(&parent) || !cut_ids.insert(parent) {
+            continue;
+        }
+        let station = by_id.get(&parent).ok_or(Error::MissingStation(parent))?;
+        let (latitude, longitude) = traverse
+            .lat_lon(parent)
+            .ok_or(Error::MissingStation(parent))?;
+        cuts.push(SurveyData {
+            station_type: "START".to_string(),
+            from_id: -1,
+            closure_to_id: -1,
+            azimuth: "0.0".to_string(),
+            length: "0.0".to_string(),
+            inclination: "0.0".to_string(),
+            latitude: format_double(latitude),
+            longitude: format_double(longitude),
+            ..(*station).clone()
+        });
+    }
+
+    let stations = |s: &&SurveyData| match s.station_type.as_str() {
+        "CLOSURE" => {
+            let kept = |id| keep.contains(&id) || cut_ids.contains(&id);
+            kept(s.from_id) && kept(s.closure_to_id)
+        }
+        _ => keep.contains(&s.id),
+    };
+    let mut data = cuts;
+    data.extend(cave.data.iter().filter(stations).cloned());
+
+    let mut cave = CaveFile {
+        info: cave.info.clone(),
+        data,
+    };
+    let ids = renumber(&mut cave, Order::File);
+    Ok(Split { cave, ids })
+}
@@ -0,0 +1,795 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+pub use rusqlite;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, Row};
+
+use crate::error::Result;
+use crate::geomag::{traverse_true_north, Model};
+use crate::loops::{loops, Loop};
+use crate::tmlu::{
+    Annotation, AnnotationField, CartoLinkedSurface, CartoOverlay, CaveFile, CaveFileInfo,
+    LayerList, RadiusVector, Shape, Style, SurveyData, UnknownElement,
+};
+use crate::traverse::Traverse;
+use crate::utils::format_double;
+
+/// The tables a cave file is stored in. `position` keeps the order of the file
+pub const SCHEMA: &str = "
+CREATE TABLE header(
+    cave_name                       TEXT,
+    first_start_absolute_elevation  REAL,      -- Elevation of the first START station
+    geo_coding                      TEXT NULL,
+    unit                            TEXT,      -- Unit of every length and depth, 'm' or 'ft'
+    use_magnetic_azimuth            TEXT       -- 'true' if the azimuths are magnetic
+);
+
+CREATE TABLE survey_data(
+    position            INTEGER PRIMARY KEY,
+    id                  INTEGER,
+    azimuth             REAL,      -- Azimuth, bearing of the leg to the station
+    closure_to_id       INTEGER,   -- Only for CLOSURE: from_id and closure_to_id are the same station
+    color               TEXT,      -- Color of the line to the station in hex ARGB (example 0xccffccff)
+    comment             TEXT NULL,
+    date                TEXT,      -- Date the station was surveyed, YYYY-MM-DD
+    depth               REAL,      -- Depth of the station, positive down
+    depth_in            REAL,      -- Depth at the start of the leg, -1.0 when not set
+    down                REAL,      -- Distance to the floor
+    excluded            TEXT,      -- 'true' if the leg is left out of the length
+    explorer            TEXT NULL, -- Explorers and surveyors, <Explorer>..</Explorer><Surveyor>..</Surveyor>
+    from_id             INTEGER,   -- Station the leg starts at, -1 for START
+    inclination         REAL,
+    latitude            REAL,      -- Only for START
+    left                REAL,      -- Distance to the left wall
+    length              REAL,      -- Length of the leg from from_id
+    locked              TEXT,
+    longitude           REAL,      -- Only for START
+    name                TEXT NULL,
+    profile_type        TEXT,      -- 'VERTICAL' or 'HORIZONTAL' passage section
+    right               REAL,      -- Distance to the right wall
+    section             TEXT NULL,
+    has_profile_azimut  TEXT,
+    has_profile_tilt    TEXT,
+    profile_azimut      REAL,
+    profile_tilt        REAL,
+    station_type        TEXT,      -- One of 'REAL', 'VIRTUAL', 'START', 'CLOSURE'
+    up                  REAL       -- Distance to the ceiling
+);
+CREATE INDEX survey_data_id ON survey_data(id);
+
+-- The passage shape of a station, one row per radius
+CREATE TABLE radius_vector(
+    position          INTEGER PRIMARY KEY,
+    station           INTEGER REFERENCES survey_data(position),
+    angle             REAL,
+    length            REAL,
+    tension_corridor  REAL,
+    tension_profile   REAL
+);
+
+-- Annotations of the drawing, with their fields in annotation_field
+CREATE TABLE annotation(
+    position  INTEGER PRIMARY KEY,
+    tag       TEXT   -- Name of the element
+);
+
+CREATE TABLE annotation_field(
+    position    INTEGER PRIMARY KEY,
+    annotation  INTEGER REFERENCES annotation(position),
+    name        TEXT,
+    value       TEXT
+);
+
+-- Drawing layers
+CREATE TABLE layer(
+    position             INTEGER PRIMARY KEY,
+    constant             TEXT,
+    locked               TEXT,
+    name                 TEXT,
+    visible              TEXT,
+    dash_scale           REAL,
+    fill_color_string    TEXT,
+    line_type            TEXT,
+    line_type_scale      REAL,
+    opacity              REAL,
+    size_mode            TEXT,
+    stroke_color_string  TEXT,
+    stroke_thickness     REAL
+);
+
+-- Image layers
+CREATE TABLE carto_overlay(
+    position   INTEGER PRIMARY KEY,
+    file_name  TEXT,
+    x          REAL,
+    y          REAL,
+    scale      REAL,
+    rotation   REAL,
+    opacity    REAL,
+    visible    TEXT
+);
+
+-- Surface layers
+CREATE TABLE carto_linked_surface(
+    position   INTEGER PRIMARY KEY,
+    file_name  TEXT,
+    name       TEXT,
+    opacity    REAL,
+    visible    TEXT
+);
+
+-- Elements this library does not know, as XML
+CREATE TABLE unknown_element(
+    position  INTEGER PRIMARY KEY,
+    station   INTEGER NULL REFERENCES survey_data(position), -- NULL for elements of CaveFile
+    after     TEXT,   -- The element it came after, empty if it was the first
+    xml       TEXT
+);
+
+-- Computed from the survey, with true north azimuths. Stations that can not be reached from a
+-- START station are left out. Lengths are in the unit of the file
+CREATE TABLE station_xyz(
+    id  INTEGER PRIMARY KEY,
+    x   REAL,   -- East of the first START station
+    y   REAL,   -- North of the first START station
+    z   REAL    -- Up, 0 at the surface
+);
+
+CREATE TABLE station_latlon(
+    id         INTEGER PRIMARY KEY,
+    latitude   REAL,
+    longitude  REAL,
+    elevation  REAL   -- firstStartAbsoluteElevation + z
+);
+
+-- One row per CLOSURE: how far apart the two stations it joins ended up
+CREATE TABLE loops(
+    closure_id      INTEGER PRIMARY KEY,
+    from_id         INTEGER,
+    to_id           INTEGER,
+    legs            INTEGER,  -- Number of legs in the loop
+    length          REAL,     -- Their total length
+    dx              REAL,
+    dy              REAL,
+    dz              REAL,
+    misclosure      REAL,
+    relative_error  REAL      -- misclosure / length
+);
+
+CREATE VIEW legs AS
+SELECT
+    s.id, s.from_id, s.station_type, s.excluded, s.section, s.date,
+    s.length, s.azimuth, s.inclination,
+    f.x AS from_x, f.y AS from_y, f.z AS from_z,
+    t.x AS to_x, t.y AS to_y, t.z AS to_z
+FROM survey_data s
+JOIN station_xyz f ON f.id = s.from_id
+JOIN station_xyz t ON t.id = s.id
+WHERE s.station_type IN ('REAL', 'VIRTUAL');
+
+-- The text of numbers that would not be written back the same from the REAL column,
+-- like '10' instead of '10.0'
+CREATE TABLE raw_text(
+    table_name   TEXT,
+    row          INTEGER,
+    column_name  TEXT,
+    text         TEXT,
+    PRIMARY KEY (table_name, row, column_name)
+);
+";
+
+// Turns the string fields into column values, remembering the numbers that need raw_text
+#[derive(Default)]
+struct Values {
+    raw: Vec<(&'static str, i64, &'static str, String)>,
+}
+
+impl Values {
+    fn number(&mut self, table: &'static str, row: i64, column: &'static str, text: &str) -> Value {
+        match text.parse::<f64>() {
+            Ok(value) if value.is_finite() && format_double(value) == text => Value::Real(value),
+            parsed => {
+                self.raw.push((table, row, column, text.to_string()));
+                parsed
+                    .or_else(|_| text.trim().parse())
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .map_or(Value::Null, Value::Real)
+            }
+        }
+    }
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+fn optional(value: &Option<String>) -> Value {
+    value.as_deref().map_or(Value::Null, text)
+}
+
+fn insert(conn: &Connection, table: &str, columns: &[&str], values: Vec<Value>) -> Result<()> {
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table,
+        columns.join(", "),
+        placeholders
+    );
+    conn.prepare_cached(&sql)?
+        .execute(params_from_iter(values))?;
+    Ok(())
+}
+
+const SURVEY_DATA: [&str; 29] = [
+    "position",
+    "id",
+    "azimuth",
+    "closure_to_id",
+    "color",
+    "comment",
+    "date",
+    "depth",
+    "depth_in",
+    "down",
+    "excluded",
+    "explorer",
+    "from_id",
+    "inclination",
+    "latitude",
+    "left",
+    "length",
+    "locked",
+    "longitude",
+    "name",
+    "profile_type",
+    "right",
+    "section",
+    "has_profile_azimut",
+    "has_profile_tilt",
+    "profile_azimut",
+    "profile_tilt",
+    "station_type",
+    "up",
+];
+
+/// Creates the tables of [`SCHEMA`] in `conn` and stores `cave` in them. The derived tables
+/// are worked out with `model` if the azimuths are magnetic, and nothing is stored if they can
+/// not be, for example when a leg's date is outside `model`
+pub fn write_database(conn: &mut Connection, cave: &CaveFile, model: &Model) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    let mut values = Values::default();
+
+    let info = &cave.info;
+    let elevation = values.number(
+        "header",
+        0,
+        "first_start_absolute_elevation",
+        &info.first_start_absolute_elevation,
+    );
+    insert(
+        &tx,
+        "header",
+        &[
+            "cave_name",
+            "first_start_absolute_elevation",
+            "geo_coding",
+            "unit",
+            "use_magnetic_azimuth",
+        ],
+        vec![
+            text(&info.cave_name),
+            elevation,
+            optional(&info.geo_coding),
+            text(&info.unit),
+            text(&info.use_magnetic_azimuth),
+        ],
+    )?;
+
+    let mut unknown = Vec::new();
+    for element in &info.unknown {
+        unknown.push((Value::Null, element));
+    }
+    let mut radius = 0;
+    for (row, srvd) in cave.data.iter().enumerate() {
+        let row = row as i64;
+        let mut number = |column, value: &str| values.number("survey_data", row, column, value);
+        let shape = &srvd.shape;
+        let row_values = vec![
+            Value::Integer(row),
+            Value::Integer(srvd.id.into()),
+            number("azimuth", &srvd.azimuth),
+            Value::Integer(srvd.closure_to_id.into()),
+            text(&srvd.color),
+            optional(&srvd.comment),
+            text(&srvd.date),
+            number("depth", &srvd.depth),
+            number("depth_in", &srvd.depth_in),
+            number("down", &srvd.down),
+            text(&srvd.excluded),
+            optional(&srvd.explorer),
+            Value::Integer(srvd.from_id.into()),
+            number("inclination", &srvd.inclination),
+            number("latitude", &srvd.latitude),
+            number("left", &srvd.left),
+            number("length", &srvd.length),
+            text(&srvd.locked),
+            number("longitude", &srvd.longitude),
+            optional(&srvd.name),
+            text(&srvd.profile_type),
+            number("right", &srvd.right),
+            optional(&srvd.section),
+            text(&shape.has_profile_azimut),
+            text(&shape.has_profile_tilt),
+            number("profile_azimut", &shape.profile_azimut),
+            number("profile_tilt", &shape.profile_tilt),
+            text(&srvd.station_type),
+            number("up", &srvd.up),
+        ];
+        insert(&tx, "survey_data", &SURVEY_DATA, row_values)?;
+
+        for rv in &shape.radius_collection {
+            let mut number =
+                |column, value: &str| values.number("radius_vector", radius, column, value);
+            let rv_values = vec![
+                Value::Integer(radius),
+                Value::Integer(row),
+                number("angle", &rv.angle),
+                number("length", &rv.length),
+                number("tension_corridor", &rv.tension_corridor),
+                number("tension_profile", &rv.tension_profile),
+            ];
+            insert(
+                &tx,
+                "radius_vector",
+                &[
+                    "position",
+                    "station",
+                    "angle",
+                    "length",
+                    "tension_corridor",
+                    "tension_profile",
+                ],
+                rv_values,
+            )?;
+            radius += 1;
+        }
+        for element in &srvd.unknown {
+            unknown.push((Value::Integer(row), element));
+        }
+    }
+
+    let mut field = 0;
+    for (row, annotation) in info.annotations.iter().enumerate() {
+        let row = row as i64;
+        insert(
+            &tx,
+            "annotation",
+            &["position", "tag"],
+            vec![Value::Integer(row), text(&annotation.tag)],
+        )?;
+        for AnnotationField { name, value } in &annotation.fields {
+            insert(
+                &tx,
+                "annotation_field",
+                &["position", "annotation", "name", "value"],
+                vec![
+                    Value::Integer(field),
+                    Value::Integer(row),
+                    text(name),
+                    text(value),
+                ],
+            )?;
+            field += 1;
+        }
+    }
+    for (row, layer) in info.layers.iter().enumerate() {
+        let row = row as i64;
+        let mut number = |column, value: &str| values.number("layer", row, column, value);
+        let style = &layer.style;
+        let layer_values = vec![
+            Value::Integer(row),
+            text(&layer.constant),
+            text(&layer.locked),
+            text(&layer.name),
+            text(&layer.visible),
+            number("dash_scale", &style.dash_scale),
+            text(&style.fill_color_string),
+            text(&style.line_type),
+            number("line_type_scale", &style.line_type_scale),
+            number("opacity", &style.opacity),
+            text(&style.size_mode),
+            text(&style.stroke_color_string),
+            number("stroke_thickness", &style.stroke_thickness),
+        ];
+        insert(
+            &tx,
+            "layer",
+            &[
+                "position",
+                "constant",
+                "locked",
+                "name",
+                "visible",
+                "dash_scale",
+                "fill_color_string",
+                "line_type",
+                "line_type_scale",
+                "opacity",
+                "size_mode",
+                "stroke_color_string",
+                "stroke_thickness",
+            ],
+            layer_values,
+        )?;
+    }
+    for (row, overlay) in info.carto_overlay.iter().enumerate() {
+        let row = row as i64;
+        let mut number = |column, value: &str| values.number("carto_overlay", row, column, value);
+        let overlay_values = vec![
+            Value::Integer(row),
+            text(&overlay.file_name),
+            number("x", &overlay.x),
+            number("y", &overlay.y),
+            number("scale", &overlay.scale),
+            number("rotation", &overlay.rotation),
+            number("opacity", &overlay.opacity),
+            text(&overlay.visible),
+        ];
+        insert(
+            &tx,
+            "carto_overlay",
+            &[
+                "position",
+                "file_name",
+                "x",
+                "y",
+                "scale",
+                "rotation",
+                "opacity",
+                "visible",
+            ],
+            overlay_values,
+        )?;
+    }
+    for (row, surface) in info.carto_linked_surface.iter().enumerate() {
+        let row = row as i64;
+        let opacity = values.number("carto_linked_surface", row, "opacity", &surface.opacity);
+        insert(
+            &tx,
+            "carto_linked_surface",
+            &["position", "file_name", "name", "opacity", "visible"],
+            vec![
+                Value::Integer(row),
+                text(&surface.file_name),
+                text(&surface.name),
+                opacity,
+                text(&surface.visible),
+            ],
+        )?;
+    }
+    for (row, (station, element)) in unknown.into_iter().enumerate() {
+        insert(
+            &tx,
+            "unknown_element",
+            &["position", "station", "after", "xml"],
+            vec![
+                Value::Integer(row as i64),
+                station,
+                text(&element.after),
+                text(&element.xml),
+            ],
+        )?;
+    }
+    let traverse = traverse_true_north(cave, model)?;
+    let loops = loops(cave, &traverse)?;
+    write_derived(&tx, cave, &traverse, &loops)?;
+    for (table, row, column, raw) in values.raw {
+        insert(
+            &tx,
+            "raw_text",
+            &["table_name", "row", "column_name", "text"],
+            vec![
+                text(table),
+                Value::Integer(row),
+                text(column),
+                Value::Text(raw),
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn write_derived(
+    conn: &Connection,
+    cave: &CaveFile,
+    traverse: &Traverse,
+    loops: &[Loop],
+) -> Result<()> {
+    let datum = cave
+        .info
+        .first_start_absolute_elevation
+        .trim()
+        .parse::<f64>()
+        .unwrap_or(0.0);
+    let closures: HashSet<i32> = cave
+        .data
+        .iter()
+        .filter(|s| s.station_type == "CLOSURE")
+        .map(|s| s.id)
+        .collect();
+    for (id, point) in traverse.iter().filter(|(id, _)| !closures.contains(id)) {
+        insert(
+            conn,
+            "station_xyz",
+            &["id", "x", "y", "z"],
+            vec![
+                Value::Integer(id.into()),
+                Value::Real(point.x),
+                Value::Real(point.y),
+                Value::Real(point.z),
+            ],
+        )?;
+        if let Some((latitude, longitude)) = traverse.lat_lon(id) {
+            insert(
+                conn,
+                "station_latlon",
+                &["id", "latitude", "longitude", "elevation"],
+                vec![
+                    Value::Integer(id.into()),
+                    Value::Real(latitude),
+                    Value::Real(longitude),
+                    Value::Real(datum + point.z),
+                ],
+            )?;
+        }
+    }
+    for l in loops {
+        let misclosure = l.misclosure;
+        insert(
+            conn,
+            "loops",
+            &[
+                "closure_id",
+                "from_id",
+                "to_id",
+                "legs",
+                "length",
+                "dx",
+                "dy",
+                "dz",
+                "misclosure",
+                "relative_error",
+            ],
+            vec![
+                Value::Integer(l.closure.into()),
+                Value::Integer(l.from_id.into()),
+                Value::Integer(l.to_id.into()),
+                Value::Integer(l.legs.len() as i64),
+                Value::Real(l.length),
+                Value::Real(misclosure.x),
+                Value::Real(misclosure.y),
+                Value::Real(misclosure.z),
+                Value::Real(misclosure.length()),
+                Value::Real(l.relative_error()),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// Reads the numbers back as the text they were written from
+struct Numbers {
+    raw: HashMap<(String, i64, String), String>,
+}
+
+impl Numbers {
+    fn get(&self, row: &Row, table: &str, key: i64, column: &str) -> rusqlite::Result<String> {
+        if let Some(raw) = self.raw.get(&(table.to_string(), key, column.to_string())) {
+            return Ok(raw.clone());
+        }
+        Ok(match row.get::<_, Value>(column)? {
+            Value::Real(value) => format_double(value),
+            Value::Integer(value) => format_double(value as f64),
+            Value::Text(value) => value,
+            Value::Null | Value::Blob(_) => String::new(),
+        })
+    }
+}
+
+/// Reads a cave file stored by [`write_database`]
+pub fn read_database(conn: &Connection) -> Result<CaveFile> {
+    let mut stmt = conn.prepare("SELECT table_name, row, column_name, text FROM raw_text")?;
+    let raw = stmt
+        .query_map([], |row| {
+            Ok(((row.get(0)?, row.get(1)?, row.get(2)?), row.get(3)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let numbers = Numbers { raw };
+
+    let mut unknown: HashMap<Option<i64>, Vec<UnknownElement>> = HashMap::new();
+    let mut stmt =
+        conn.prepare("SELECT station, after, xml FROM unknown_element ORDER BY position")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        unknown
+            .entry(row.get(0)?)
+            .or_default()
+            .push(UnknownElement {
+                after: row.get(1)?,
+                xml: row.get(2)?,
+            });
+    }
+
+    let mut radii: HashMap<i64, Vec<RadiusVector>> = HashMap::new();
+    let mut stmt = conn.prepare("SELECT * FROM radius_vector ORDER BY position")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let key: i64 = row.get("position")?;
+        let get = |column| numbers.get(row, "radius_vector", key, column);
+        radii
+            .entry(row.get("station")?)
+            .or_default()
+            .push(RadiusVector {
+                angle: get("angle")?,
+                length: get("length")?,
+                tension_corridor: get("tension_corridor")?,
+                tension_profile: get("tension_profile")?,
+            });
+    }
+
+    let mut fields: HashMap<i64, Vec<AnnotationField>> = HashMap::new();
+    let mut stmt = conn.prepare("SELECT * FROM annotation_field ORDER BY position")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        fields
+            .entry(row.get("annotation")?)
+            .or_default()
+            .push(AnnotationField {
+                name: row.get("name")?,
+                value: row.get("value")?,
+            });
+    }
+    let mut stmt = conn.prepare("SELECT * FROM annotation ORDER BY position")?;
+    let annotations = stmt
+        .query_map([], |row| {
+            Ok(Annotation {
+                tag: row.get("tag")?,
+                fields: fields.remove(&row.get("position")?).unwrap_or_default(),
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare("SELECT * FROM layer ORDER BY position")?;
+    let layers = stmt
+        .query_map([], |row| {
+            let key: i64 = row.get("position")?;
+            let get = |column| numbers.get(row, "layer", key, column);
+            Ok(LayerList {
+                constant: row.get("constant")?,
+                locked: row.get("locked")?,
+                name: row.get("name")?,
+                style: Style {
+                    dash_scale: get("dash_scale")?,
+                    fill_color_string: row.get("fill_color_string")?,
+                    line_type: row.get("line_type")?,
+                    line_type_scale: get("line_type_scale")?,
+                    opacity: get("opacity")?,
+                    size_mode: row.get("size_mode")?,
+                    stroke_color_string: row.get("stroke_color_string")?,
+                    stroke_thickness: get("stroke_thickness")?,
+                },
+                visible: row.get("visible")?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare("SELECT * FROM carto_overlay ORDER BY position")?;
+    let carto_overlay = stmt
+        .query_map([], |row| {
+            let key: i64 = row.get("position")?;
+            let get = |column| numbers.get(row, "carto_overlay", key, column);
+            Ok(CartoOverlay {
+                file_name: row.get("file_name")?,
+                x: get("x")?,
+                y: get("y")?,
+                scale: get("scale")?,
+                rotation: get("rotation")?,
+                opacity: get("opacity")?,
+                visible: row.get("visible")?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut stmt = conn.prepare("SELECT * FROM carto_linked_surface ORDER BY position")?;
+    let carto_linked_surface = stmt
+        .query_map([], |row| {
+            let key: i64 = row.get("position")?;
+            Ok(CartoLinkedSurface {
+                file_name: row.get("file_name")?,
+                name: row.get("name")?,
+                opacity: numbers.get(row, "carto_linked_surface", key, "opacity")?,
+                visible: row.get("visible")?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare("SELECT * FROM header")?;
+    let mut rows = stmt.query([])?;
+    let mut info = match rows.next()? {
+        Some(row) => CaveFileInfo {
+            cave_name: row.get("cave_name")?,
+            first_start_absolute_elevation: numbers.get(
+                row,
+                "header",
+                0,
+                "first_start_absolute_elevation",
+            )?,
+            geo_coding: row.get("geo_coding")?,
+            unit: row.get("unit")?,
+            use_magnetic_azimuth: row.get("use_magnetic_azimuth")?,
+            ..Default::default()
+        },
+        None => CaveFileInfo::default(),
+    };
+    info.annotations = annotations;
+    info.layers = layers;
+    info.carto_overlay = carto_overlay;
+    info.carto_linked_surface = carto_linked_surface;
+    info.unknown = unknown.remove(&None).unwrap_or_default();
+
+    let mut stmt = conn.prepare("SELECT * FROM survey_data ORDER BY position")?;
+    let mut rows = stmt.query([])?;
+    let mut data = Vec::new();
+    while let Some(row) = rows.next()? {
+        let key: i64 = row.get("position")?;
+        let get = |column| numbers.get(row, "survey_data", key, column);
+        data.push(SurveyData {
+            id: row.get("id")?,
+            azimuth: get("azimuth")?,
+            closure_to_id: row.get("closure_to_id")?,
+            color: row.get("color")?,
+            comment: row.get("comment")?,
+            date: row.get("date")?,
+            depth: get("depth")?,
+            depth_in: get("depth_in")?,
+            down: get("down")?,
+            excluded: row.get("excluded")?,
+            explorer: row.get("explorer")?,
+            from_id: row.get("from_id")?,
+            inclination: get("inclination")?,
+            latitude: get("latitude")?,
+            left: get("left")?,
+            length: get("length")?,
+            locked: row.get("locked")?,
+            longitude: get("longitude")?,
+            name: row.get("name")?,
+            profile_type: row.get("profile_type")?,
+            right: get("right")?,
+            section: row.get("section")?,
+            shape: Shape {
+                has_profile_azimut: row.get("has_profile_azimut")?,
+                has_profile_tilt: row.get("has_profile_tilt")?,
+                profile_azimut: get("profile_azimut")?,
+                profile_tilt: get("profile_tilt")?,
+                radius_collection: radii.remove(&key).unwrap_or_default(),
+            },
+            station_type: row.get("station_type")?,
+            up: get("up")?,
+            unknown: unknown.remove(&Some(key)).unwrap_or_default(),
+        });
+    }
+    Ok(CaveFile { info, data })
+}
+
+/// Writes `cave` to a new SQLite database at `path`
+pub fn save(path: impl AsRef<Path>, cave: &CaveFile, model: &Model) -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    write_database(&mut conn, cave, model)
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<CaveFile> {
+    read_database(&Connection::open(path)?)
+}
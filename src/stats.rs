@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::Result;
+use crate::geomag::{traverse_true_north, Model};
+use crate::people::same_person;
+use crate::tmlu::CaveFile;
+use crate::utils::{clean_name, parse_double, section_name};
+
+/// A number of legs and their total length
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Tally {
+    pub legs: usize,
+    pub length: f64,
+}
+
+impl Tally {
+    fn add(&mut self, length: f64) {
+        self.legs += 1;
+        self.length += length;
+    }
+}
+
+/// Summary of a survey. Lengths and depths are in `unit`
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct Stats {
+    pub unit: String,
+    /// REAL legs that are not excluded
+    pub surveyed: Tally,
+    /// REAL legs marked as excluded
+    pub excluded: Tally,
+    #[cfg_attr(feature = "serde", serde(rename = "virtual"))]
+    pub virtual_legs: Tally,
+    /// Every station except CLOSURE rows
+    pub stations: usize,
+    /// CLOSURE rows
+    pub loops: usize,
+    pub max_depth: f64,
+    /// From the shallowest to the deepest station
+    pub vertical_range: f64,
+    pub east_west: f64,
+    pub north_south: f64,
+    /// The longest horizontal distance between two stations
+    pub horizontal_extent: f64,
+    /// Surveyed legs by section, year of the date and explorer. A leg counts for each of its
+    /// explorers, names that only differ in spacing or case are counted together
+    pub by_section: BTreeMap<String, Tally>,
+    pub by_year: BTreeMap<String, Tally>,
+    pub by_explorer: BTreeMap<String, Tally>,
+}
+
+fn year(date: &str) -> &str {
+    match date.trim().get(..4) {
+        Some(year) if year.bytes().all(|b| b.is_ascii_digit()) => year,
+        _ => "",
+    }
+}
+
+fn cross(o: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+// The longest distance between two points is between two corners of their convex hull.
+// Points that are not finite are left out
+fn diameter(mut points: Vec<(f64, f64)>) -> f64 {
+    points.retain(|p| p.0.is_finite() && p.1.is_finite());
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    points.dedup();
+    let mut hull: Vec<(f64, f64)> = Vec::new();
+    for pass in [
+        &points[..],
+        &points.iter().rev().copied().collect::<Vec<_>>()[..],
+    ] {
+        let bottom = hull.len();
+        for &p in pass {
+            while hull.len() >= bottom + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        hull.pop();
+    }
+    let mut longest: f64 = 0.0;
+    for (i, a) in hull.iter().enumerate() {
+        for b in &hull[i + 1..] {
+            longest = longest.max((a.0 - b.0).hypot(a.1 - b.1));
+        }
+    }
+    longest
+}
+
+/// Lengths, depths, extents and counts for a survey. The extents are of the stations that can
+/// be reached from a START station, with magnetic azimuths corrected with `model`
+pub fn stats(cave: &CaveFile, model: &Model) -> Result<Stats> {
+    let mut stats = Stats {
+        unit: cave.info.unit.clone(),
+        ..Default::default()
+    };
+    let (mut shallowest, mut deepest) = (f64::INFINITY, f64::NEG_INFINITY);
+    let mut explorer_names: HashMap<String, String> = HashMap::new();
+    for srvd in &cave.data {
+        let id = Some(srvd.id);
+        if srvd.station_type == "CLOSURE" {
+            stats.loops += 1;
+            continue;
+        }
+        stats.stations += 1;
+        let depth = parse_double(&srvd.depth, id, "DP")?;
+        shallowest = shallowest.min(depth);
+        deepest = deepest.max(depth);
+
+        let length = match srvd.station_type.as_str() {
+            "START" => continue,
+            "VIRTUAL" => {
+                stats
+                    .virtual_legs
+                    .add(parse_double(&srvd.length, id, "LG")?);
+                continue;
+            }
+            _ => parse_double(&srvd.length, id, "LG")?,
+        };
+        if srvd.excluded.trim() == "true" {
+            stats.excluded.add(length);
+            continue;
+        }
+        stats.surveyed.add(length);
+        let section = section_name(srvd.section.as_deref().unwrap_or_default());
+        stats
+            .by_section
+            .entry(section.to_string())
+            .or_default()
+            .add(length);
+        stats
+            .by_year
+            .entry(year(&srvd.date).to_string())
+            .or_default()
+            .add(length);
+        let mut explorers = srvd.team().explorers;
+        explorers.sort_by_key(|n| n.to_lowercase());
+        explorers.dedup_by(|a, b| same_person(a, b));
+        for explorer in explorers {
+            let name = explorer_names
+                .entry(clean_name(&explorer).to_lowercase())
+                .or_insert(explorer);
+            stats
+                .by_explorer
+                .entry(name.clone())
+                .or_default()
+                .add(length);
+        }
+    }
+    if stats.stations > 0 {
+        stats.max_depth = deepest;
+        stats.vertical_range = deepest - shallowest;
+    }
+
+    let traverse = traverse_true_north(cave, model)?;
+    let points: Vec<(f64, f64)> = traverse.iter().map(|(_, p)| (p.x, p.y)).collect();
+    if !points.is_empty() {
+        let span = |values: &mut dyn Iterator<Item = f64>| {
+            let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+            max - min
+        };
+        stats.east_west = span(&mut points.iter().map(|p| p.0));
+        stats.north_south = span(&mut points.iter().map(|p| p.1));
+        stats.horizontal_extent = diameter(points);
+    }
+    Ok(stats)
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::{Error, Result};
+use crate::tmlu::{try_read_cavefile, write_cavefile, CaveFile, CaveFileInfo, SurveyData};
+
+/// Name of the xml file inside a `.tml` container
+pub const ENTRY_NAME: &str = "Data.xml";
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Plain xml
+    Tmlu,
+    /// Zip container with the xml inside
+    Tml,
+}
+
+impl Format {
+    /// Detects the format from the first bytes of a file
+    pub fn detect(header: &[u8]) -> Format {
+        if header.starts_with(ZIP_MAGIC) {
+            Format::Tml
+        } else {
+            Format::Tmlu
+        }
+    }
+
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Format {
+        match path.as_ref().extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("tml") => Format::Tml,
+            _ => Format::Tmlu,
+        }
+    }
+}
+
+pub fn read_tml<R: Read + Seek>(input: R) -> Result<CaveFile> {
+    let mut archive = ZipArchive::new(input)?;
+    let index = xml_entry(&mut archive)?;
+    let entry = archive.by_index(index)?;
+    try_read_cavefile(BufReader::new(entry))
+}
+
+// Prefers an entry that looks like xml, falls back to the first file
+fn xml_entry<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<usize> {
+    let mut first_file = None;
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let name = entry.name().to_ascii_lowercase();
+        if name.ends_with(".xml") || name.ends_with(".tmlu") {
+            return Ok(i);
+        }
+        first_file.get_or_insert(i);
+    }
+    first_file.ok_or(Error::Zip(ZipError::FileNotFound))
+}
+
+pub fn write_tml<W: Write + Seek, I: IntoIterator<Item = SurveyData>>(
+    output: W,
+    survey_data: I,
+    info: CaveFileInfo,
+) -> Result<()> {
+    let mut zip = ZipWriter::new(output);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file(ENTRY_NAME, options)?;
+    write_cavefile(&mut zip, survey_data, info)?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Reads a `.tmlu` or `.tml` file, detecting which from its first bytes
+pub fn read_any<R: BufRead + Seek>(mut input: R) -> Result<CaveFile> {
+    match Format::detect(input.fill_buf()?) {
+        Format::Tml => read_tml(input),
+        Format::Tmlu => try_read_cavefile(input),
+    }
+}
+
+pub fn open<P: AsRef<Path>>(path: P) -> Result<CaveFile> {
+    read_any(BufReader::new(File::open(path)?))
+}
+
+/// Writes a `.tml` container if the extension of `path` is `.tml`, otherwise plain xml
+pub fn save<P: AsRef<Path>, I: IntoIterator<Item = SurveyData>>(
+    path: P,
+    survey_data: I,
+    info: CaveFileInfo,
+) -> Result<()> {
+    let output = BufWriter::new(File::create(&path)?);
+    match Format::from_extension(&path) {
+        Format::Tml => write_tml(output, survey_data, info),
+        Format::Tmlu => Ok(write_cavefile(output, survey_data, info)?),
+    }
+}
@@ -1,11 +1,16 @@
+use crate::error::{Error, Result};
+use crate::utils::{format_double, Team};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::borrow::Cow;
+use std::io::{BufRead, BufWriter, Read, Seek, SeekFrom, Write};
 use std::option::Option;
-use xml::{
-    common::XmlVersion,
-    writer::{EmitterConfig, XmlEvent},
-    EventWriter,
-};
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+/// How a drawing layer is drawn
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
 pub struct Style {
     pub dash_scale: String,
     pub fill_color_string: String,
@@ -17,17 +22,20 @@ pub struct Style {
     pub stroke_thickness: String,
 }
 
-#[derive(Debug, Clone)]
-struct LayerList {
-    constant: String,
-    locked: String,
-    name: String,
-    style: Style,
-    visible: String,
+/// A drawing layer, `Overlay` and `Default` in a new file
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
+pub struct LayerList {
+    pub constant: String,
+    pub locked: String,
+    pub name: String,
+    pub style: Style,
+    pub visible: String,
 }
 
 impl LayerList {
-    fn new(name: &str) -> LayerList {
+    pub fn new(name: &str) -> LayerList {
         LayerList {
             constant: "true".to_string(),
             locked: "false".to_string(),
@@ -36,6 +44,22 @@ impl LayerList {
             visible: "true".to_string(),
         }
     }
+
+    fn update(&mut self, tag: &[u8], val: String) {
+        match tag {
+            b"constant" => self.constant = val,
+            b"locked" => self.locked = val,
+            b"name" => self.name = val,
+            b"visible" => self.visible = val,
+            _ => (),
+        }
+    }
+}
+
+impl Default for LayerList {
+    fn default() -> LayerList {
+        LayerList::new("")
+    }
 }
 
 impl Default for Style {
@@ -53,7 +77,43 @@ impl Default for Style {
     }
 }
 
-#[derive(Debug, Clone)]
+impl Style {
+    fn update(&mut self, tag: &[u8], val: String) {
+        match tag {
+            b"dashScale" => self.dash_scale = val,
+            b"fillColorString" => self.fill_color_string = val,
+            b"lineType" => self.line_type = val,
+            b"lineTypeScale" => self.line_type_scale = val,
+            b"opacity" => self.opacity = val,
+            b"sizeMode" => self.size_mode = val,
+            b"strokeColorString" => self.stroke_color_string = val,
+            b"strokeThickness" => self.stroke_thickness = val,
+            _ => (),
+        }
+    }
+}
+
+/// An annotation of the drawing, an element of `ListAnnotation` with its fields in file order
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
+pub struct Annotation {
+    /// Name of the element
+    pub tag: String,
+    pub fields: Vec<AnnotationField>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
+pub struct AnnotationField {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
 pub struct RadiusVector {
     pub angle: String,
     pub length: String,
@@ -64,14 +124,16 @@ pub struct RadiusVector {
 impl RadiusVector {
     pub fn default(angle: String) -> RadiusVector {
         RadiusVector {
-            angle: angle,
+            angle,
             length: "0.0".to_string(),
             tension_corridor: "1.0".to_string(),
             tension_profile: "1.0".to_string(),
         }
     }
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
 pub struct Shape {
     pub has_profile_azimut: String,
     pub has_profile_tilt: String,
@@ -95,7 +157,202 @@ impl Default for Shape {
         }
     }
 }
+
+impl Shape {
+    // Called for elements opening inside SH
+    fn start(&mut self, tag: &[u8]) {
+        match tag {
+            b"RC" => self.radius_collection.clear(),
+            b"RV" => self
+                .radius_collection
+                .push(RadiusVector::default("0.0".to_string())),
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, tag: &[u8], val: String) {
+        match tag {
+            b"HPRA" => self.has_profile_azimut = val,
+            b"HPRT" => self.has_profile_tilt = val,
+            b"PRAZ" => self.profile_azimut = val,
+            b"PRT" => self.profile_tilt = val,
+            _ => {
+                if let Some(rv) = self.radius_collection.last_mut() {
+                    match tag {
+                        b"ag" => rv.angle = val,
+                        b"lg" => rv.length = val,
+                        b"tc" => rv.tension_corridor = val,
+                        b"tp" => rv.tension_profile = val,
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Borrowed version of [`RadiusVector`]
 #[derive(Debug, Clone)]
+pub struct RadiusVectorRef<'a> {
+    pub angle: Cow<'a, str>,
+    pub length: Cow<'a, str>,
+    pub tension_corridor: Cow<'a, str>,
+    pub tension_profile: Cow<'a, str>,
+}
+
+impl<'a> RadiusVectorRef<'a> {
+    fn default(angle: &'a str) -> RadiusVectorRef<'a> {
+        RadiusVectorRef {
+            angle: Cow::Borrowed(angle),
+            length: Cow::Borrowed("0.0"),
+            tension_corridor: Cow::Borrowed("1.0"),
+            tension_profile: Cow::Borrowed("1.0"),
+        }
+    }
+
+    pub fn into_owned(self) -> RadiusVector {
+        RadiusVector {
+            angle: self.angle.into_owned(),
+            length: self.length.into_owned(),
+            tension_corridor: self.tension_corridor.into_owned(),
+            tension_profile: self.tension_profile.into_owned(),
+        }
+    }
+}
+
+/// Borrowed version of [`Shape`]
+#[derive(Debug, Clone)]
+pub struct ShapeRef<'a> {
+    pub has_profile_azimut: Cow<'a, str>,
+    pub has_profile_tilt: Cow<'a, str>,
+    pub profile_azimut: Cow<'a, str>,
+    pub profile_tilt: Cow<'a, str>,
+    pub radius_collection: Vec<RadiusVectorRef<'a>>,
+}
+
+impl Default for ShapeRef<'_> {
+    fn default() -> Self {
+        ShapeRef {
+            has_profile_azimut: Cow::Borrowed("false"),
+            has_profile_tilt: Cow::Borrowed("false"),
+            profile_azimut: Cow::Borrowed("0.0"),
+            profile_tilt: Cow::Borrowed("0.0"),
+            radius_collection: vec![
+                RadiusVectorRef::default("0.0"),
+                RadiusVectorRef::default("180.0"),
+                RadiusVectorRef::default("90.0"),
+                RadiusVectorRef::default("270.0"),
+            ],
+        }
+    }
+}
+
+impl<'a> ShapeRef<'a> {
+    fn start(&mut self, tag: &[u8]) {
+        match tag {
+            b"RC" => self.radius_collection.clear(),
+            b"RV" => self.radius_collection.push(RadiusVectorRef::default("0.0")),
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, tag: &[u8], val: Cow<'a, str>) {
+        match tag {
+            b"HPRA" => self.has_profile_azimut = val,
+            b"HPRT" => self.has_profile_tilt = val,
+            b"PRAZ" => self.profile_azimut = val,
+            b"PRT" => self.profile_tilt = val,
+            _ => {
+                if let Some(rv) = self.radius_collection.last_mut() {
+                    match tag {
+                        b"ag" => rv.angle = val,
+                        b"lg" => rv.length = val,
+                        b"tc" => rv.tension_corridor = val,
+                        b"tp" => rv.tension_profile = val,
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn into_owned(self) -> Shape {
+        Shape {
+            has_profile_azimut: self.has_profile_azimut.into_owned(),
+            has_profile_tilt: self.has_profile_tilt.into_owned(),
+            profile_azimut: self.profile_azimut.into_owned(),
+            profile_tilt: self.profile_tilt.into_owned(),
+            radius_collection: self
+                .radius_collection
+                .into_iter()
+                .map(RadiusVectorRef::into_owned)
+                .collect(),
+        }
+    }
+}
+
+/// An element this crate does not understand, kept as raw xml so a rewrite does not lose it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct UnknownElement {
+    /// Name of the known element it followed, empty if it was the first child. Inside the
+    /// shape of a station it is a path: `SH/PRT`, `SH/` for the first child of `SH`, and
+    /// `SH/RC/RV[2]/ag` or `SH/RC/RV[2]` for the third radius vector and what follows it
+    pub after: String,
+    pub xml: String,
+}
+
+impl UnknownElement {
+    pub fn name(&self) -> &str {
+        let tag = self.xml.trim_start().trim_start_matches('<');
+        let end = tag
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(tag.len());
+        &tag[..end]
+    }
+}
+
+const SRVD_ELEMENTS: [&[u8]; 25] = [
+    b"AZ", b"CID", b"CL", b"CM", b"DT", b"DP", b"DPI", b"D", b"EXC", b"EX", b"FRID", b"ID", b"INC",
+    b"LT", b"L", b"LG", b"LK", b"LGT", b"NM", b"PRTY", b"R", b"SC", b"SH", b"TY", b"U",
+];
+
+const CAVEFILE_ELEMENTS: [&[u8]; 17] = [
+    b"caveName",
+    b"firstStartAbsoluteElevation",
+    b"geoCoding",
+    b"ListAnnotation",
+    b"Data",
+    b"unit",
+    b"useMagneticAzimuth",
+    b"Constraints",
+    b"CartoLine",
+    b"CartoPage",
+    b"CartoRectangle",
+    b"CartoSelection",
+    b"CartoEllipse",
+    b"CartoSpline",
+    b"Layers",
+    b"CartoOverlay",
+    b"CartoLinkedSurface",
+];
+
+// Elements of CaveFile the model has no fields for. They are written empty, or as they were
+// read if there was anything in them
+const UNMODELED_ELEMENTS: [&[u8]; 7] = [
+    b"Constraints",
+    b"CartoLine",
+    b"CartoPage",
+    b"CartoRectangle",
+    b"CartoSelection",
+    b"CartoEllipse",
+    b"CartoSpline",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
 pub struct SurveyData {
     pub id: i32,
     pub azimuth: String,
@@ -122,6 +379,7 @@ pub struct SurveyData {
     pub shape: Shape,
     pub station_type: String,
     pub up: String,
+    pub unknown: Vec<UnknownElement>,
 }
 impl Default for SurveyData {
     fn default() -> Self {
@@ -151,17 +409,82 @@ impl Default for SurveyData {
             shape: Shape::default(),
             station_type: "0".to_string(),
             up: "0.0".to_string(),
+            unknown: Vec::new(),
         }
     }
 }
 
 impl SurveyData {
-    fn update(&mut self, tag: &Vec<u8>, e: &quick_xml::events::BytesText) {
-        let val = e.unescape().unwrap().into_owned();
-        match tag.as_slice() {
-            b"ID" => self.id = val.parse::<i32>().ok().unwrap(),
+    /// A CLOSURE row telling that `from_id` and `closure_to_id` are the same point
+    pub fn closure(id: i32, from_id: i32, closure_to_id: i32, date: &str) -> SurveyData {
+        SurveyData {
+            id,
+            from_id,
+            closure_to_id,
+            color: "0x00000000".to_string(),
+            comment: Some("CLOSURE".to_string()),
+            date: date.to_string(),
+            name: Some("CLOSURE:CLOSURE".to_string()),
+            profile_type: "VERTICAL".to_string(),
+            section: Some(String::new()),
+            station_type: "CLOSURE".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A START station at `latitude` and `longitude`, in degrees
+    pub fn start(id: i32, latitude: f64, longitude: f64, date: &str) -> SurveyData {
+        SurveyData {
+            id,
+            from_id: -1,
+            closure_to_id: -1,
+            color: "0xffffffff".to_string(),
+            date: date.to_string(),
+            latitude: format_double(latitude),
+            locked: "true".to_string(),
+            longitude: format_double(longitude),
+            profile_type: "VERTICAL".to_string(),
+            station_type: "START".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A REAL station surveyed from `from`, with its date, depth, section, team and color.
+    /// The leg has no length yet
+    pub fn real(id: i32, from: &SurveyData) -> SurveyData {
+        SurveyData {
+            id,
+            from_id: from.id,
+            closure_to_id: -1,
+            color: from.color.clone(),
+            date: from.date.clone(),
+            depth: from.depth.clone(),
+            depth_in: "-1.0".to_string(),
+            explorer: from.explorer.clone(),
+            profile_type: "VERTICAL".to_string(),
+            section: from.section.clone(),
+            station_type: "REAL".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The explorers and surveyors from `explorer`
+    pub fn team(&self) -> Team {
+        Team::parse(self.explorer.as_deref().unwrap_or_default())
+    }
+
+    pub fn set_team(&mut self, team: &Team) {
+        self.explorer = Some(team.to_ariane());
+    }
+}
+
+impl Station<'_> for SurveyData {
+    fn update(&mut self, tag: &[u8], val: Cow<'_, str>, position: usize) -> Result<()> {
+        let val = val.into_owned();
+        match tag {
+            b"ID" => self.id = parse_id(tag, val, position)?,
             b"AZ" => self.azimuth = val,
-            b"CID" => self.closure_to_id = val.parse::<i32>().ok().unwrap(),
+            b"CID" => self.closure_to_id = parse_id(tag, val, position)?,
             b"CL" => self.color = val,
             b"CM" => self.comment = Some(val),
             b"DT" => self.date = val,
@@ -170,7 +493,7 @@ impl SurveyData {
             b"D" => self.down = val,
             b"EXC" => self.excluded = val,
             b"EX" => self.explorer = Some(val),
-            b"FRID" => self.from_id = val.parse::<i32>().ok().unwrap(),
+            b"FRID" => self.from_id = parse_id(tag, val, position)?,
             b"INC" => self.inclination = val,
             b"LT" => self.latitude = val,
             b"L" => self.left = val,
@@ -183,17 +506,270 @@ impl SurveyData {
             b"SC" => self.section = Some(val),
             b"TY" => self.station_type = val,
             b"U" => self.up = val,
-            _ => (),
+            _ => self.shape.update(tag, val),
         }
+        Ok(())
+    }
+
+    fn start(&mut self, tag: &[u8]) {
+        self.shape.start(tag)
+    }
+
+    fn unknown(&mut self) -> &mut Vec<UnknownElement> {
+        &mut self.unknown
     }
 }
 
+/// Borrowed version of [`SurveyData`], see [`read_cavefile_ref`]
 #[derive(Debug, Clone)]
+pub struct SurveyDataRef<'a> {
+    pub id: i32,
+    pub azimuth: Cow<'a, str>,
+    pub closure_to_id: i32,
+    pub color: Cow<'a, str>,
+    pub comment: Option<Cow<'a, str>>,
+    pub date: Cow<'a, str>,
+    pub depth: Cow<'a, str>,
+    pub depth_in: Cow<'a, str>,
+    pub down: Cow<'a, str>,
+    pub excluded: Cow<'a, str>,
+    pub explorer: Option<Cow<'a, str>>,
+    pub from_id: i32,
+    pub inclination: Cow<'a, str>,
+    pub latitude: Cow<'a, str>,
+    pub left: Cow<'a, str>,
+    pub length: Cow<'a, str>,
+    pub locked: Cow<'a, str>,
+    pub longitude: Cow<'a, str>,
+    pub name: Option<Cow<'a, str>>,
+    pub profile_type: Cow<'a, str>,
+    pub right: Cow<'a, str>,
+    pub section: Option<Cow<'a, str>>,
+    pub shape: ShapeRef<'a>,
+    pub station_type: Cow<'a, str>,
+    pub up: Cow<'a, str>,
+    pub unknown: Vec<UnknownElement>,
+}
+
+impl<'a> Station<'a> for SurveyDataRef<'a> {
+    fn update(&mut self, tag: &[u8], val: Cow<'a, str>, position: usize) -> Result<()> {
+        match tag {
+            b"ID" => self.id = parse_id(tag, val.into_owned(), position)?,
+            b"AZ" => self.azimuth = val,
+            b"CID" => self.closure_to_id = parse_id(tag, val.into_owned(), position)?,
+            b"CL" => self.color = val,
+            b"CM" => self.comment = Some(val),
+            b"DT" => self.date = val,
+            b"DP" => self.depth = val,
+            b"DPI" => self.depth_in = val,
+            b"D" => self.down = val,
+            b"EXC" => self.excluded = val,
+            b"EX" => self.explorer = Some(val),
+            b"FRID" => self.from_id = parse_id(tag, val.into_owned(), position)?,
+            b"INC" => self.inclination = val,
+            b"LT" => self.latitude = val,
+            b"L" => self.left = val,
+            b"LG" => self.length = val,
+            b"LK" => self.locked = val,
+            b"LGT" => self.longitude = val,
+            b"NM" => self.name = Some(val),
+            b"PRTY" => self.profile_type = val,
+            b"R" => self.right = val,
+            b"SC" => self.section = Some(val),
+            b"TY" => self.station_type = val,
+            b"U" => self.up = val,
+            _ => self.shape.update(tag, val),
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, tag: &[u8]) {
+        self.shape.start(tag)
+    }
+
+    fn unknown(&mut self) -> &mut Vec<UnknownElement> {
+        &mut self.unknown
+    }
+}
+
+impl Default for SurveyDataRef<'_> {
+    fn default() -> Self {
+        SurveyDataRef {
+            id: 0,
+            azimuth: Cow::Borrowed("0.0"),
+            closure_to_id: 0,
+            color: Cow::Borrowed("0"),
+            comment: None,
+            date: Cow::Borrowed("2021-01-01"),
+            depth: Cow::Borrowed("0.0"),
+            depth_in: Cow::Borrowed("0.0"),
+            down: Cow::Borrowed("0.0"),
+            excluded: Cow::Borrowed("false"),
+            explorer: None,
+            from_id: 0,
+            inclination: Cow::Borrowed("0.0"),
+            latitude: Cow::Borrowed("0.0"),
+            left: Cow::Borrowed("0.0"),
+            length: Cow::Borrowed("0.0"),
+            locked: Cow::Borrowed("false"),
+            longitude: Cow::Borrowed("0.0"),
+            name: None,
+            profile_type: Cow::Borrowed("0"),
+            right: Cow::Borrowed("0.0"),
+            section: None,
+            shape: ShapeRef::default(),
+            station_type: Cow::Borrowed("0"),
+            up: Cow::Borrowed("0.0"),
+            unknown: Vec::new(),
+        }
+    }
+}
+
+impl SurveyDataRef<'_> {
+    pub fn into_owned(self) -> SurveyData {
+        SurveyData {
+            id: self.id,
+            azimuth: self.azimuth.into_owned(),
+            closure_to_id: self.closure_to_id,
+            color: self.color.into_owned(),
+            comment: self.comment.map(Cow::into_owned),
+            date: self.date.into_owned(),
+            depth: self.depth.into_owned(),
+            depth_in: self.depth_in.into_owned(),
+            down: self.down.into_owned(),
+            excluded: self.excluded.into_owned(),
+            explorer: self.explorer.map(Cow::into_owned),
+            from_id: self.from_id,
+            inclination: self.inclination.into_owned(),
+            latitude: self.latitude.into_owned(),
+            left: self.left.into_owned(),
+            length: self.length.into_owned(),
+            locked: self.locked.into_owned(),
+            longitude: self.longitude.into_owned(),
+            name: self.name.map(Cow::into_owned),
+            profile_type: self.profile_type.into_owned(),
+            right: self.right.into_owned(),
+            section: self.section.map(Cow::into_owned),
+            shape: self.shape.into_owned(),
+            station_type: self.station_type.into_owned(),
+            up: self.up.into_owned(),
+            unknown: self.unknown,
+        }
+    }
+}
+
+fn parse_id(tag: &[u8], val: String, position: usize) -> Result<i32> {
+    val.parse::<i32>().map_err(|_| Error::InvalidValue {
+        position,
+        tag: String::from_utf8_lossy(tag).into_owned(),
+        value: val,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
+pub struct CartoOverlay {
+    pub file_name: String,
+    pub x: String,
+    pub y: String,
+    pub scale: String,
+    pub rotation: String,
+    pub opacity: String,
+    pub visible: String,
+}
+
+impl Default for CartoOverlay {
+    fn default() -> CartoOverlay {
+        CartoOverlay {
+            file_name: "".to_string(),
+            x: "0.0".to_string(),
+            y: "0.0".to_string(),
+            scale: "1.0".to_string(),
+            rotation: "0.0".to_string(),
+            opacity: "100.0".to_string(),
+            visible: "true".to_string(),
+        }
+    }
+}
+
+impl CartoOverlay {
+    fn update(&mut self, tag: &[u8], val: String) {
+        match tag {
+            b"fileName" => self.file_name = val,
+            b"x" => self.x = val,
+            b"y" => self.y = val,
+            b"scale" => self.scale = val,
+            b"rotation" => self.rotation = val,
+            b"opacity" => self.opacity = val,
+            b"visible" => self.visible = val,
+            _ => (),
+        }
+    }
+
+    /// Path of the overlay image, relative paths are resolved against the directory of `tmlu_path`
+    pub fn resolve_path<P: AsRef<Path>>(&self, tmlu_path: P) -> PathBuf {
+        resolve_linked_path(&self.file_name, tmlu_path.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
+pub struct CartoLinkedSurface {
+    pub file_name: String,
+    pub name: String,
+    pub opacity: String,
+    pub visible: String,
+}
+
+impl Default for CartoLinkedSurface {
+    fn default() -> CartoLinkedSurface {
+        CartoLinkedSurface {
+            file_name: "".to_string(),
+            name: "".to_string(),
+            opacity: "100.0".to_string(),
+            visible: "true".to_string(),
+        }
+    }
+}
+
+impl CartoLinkedSurface {
+    fn update(&mut self, tag: &[u8], val: String) {
+        match tag {
+            b"fileName" => self.file_name = val,
+            b"name" => self.name = val,
+            b"opacity" => self.opacity = val,
+            b"visible" => self.visible = val,
+            _ => (),
+        }
+    }
+
+    /// Path of the surface data, relative paths are resolved against the directory of `tmlu_path`
+    pub fn resolve_path<P: AsRef<Path>>(&self, tmlu_path: P) -> PathBuf {
+        resolve_linked_path(&self.file_name, tmlu_path.as_ref())
+    }
+}
+
+fn resolve_linked_path(file_name: &str, tmlu_path: &Path) -> PathBuf {
+    let path = Path::new(file_name);
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    match tmlu_path.parent() {
+        Some(dir) => dir.join(path),
+        None => path.to_path_buf(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, rename_all = "camelCase"))]
 pub struct CaveFileInfo {
     pub cave_name: String,
     pub first_start_absolute_elevation: String,
-    pub geo_coding: String,
-    //list_annotation: String,
+    pub geo_coding: Option<String>,
+    pub annotations: Vec<Annotation>,
     //data: String,
     pub unit: String,
     pub use_magnetic_azimuth: String,
@@ -204,9 +780,10 @@ pub struct CaveFileInfo {
     // carto_selection: String,
     // carto_ellipse: String,
     // carto_spline: String,
-    // layers: String,
-    // carto_overlay: String,
-    // carto_linked_surface: String,
+    pub layers: Vec<LayerList>,
+    pub carto_overlay: Vec<CartoOverlay>,
+    pub carto_linked_surface: Vec<CartoLinkedSurface>,
+    pub unknown: Vec<UnknownElement>,
 }
 
 impl Default for CaveFileInfo {
@@ -214,8 +791,8 @@ impl Default for CaveFileInfo {
         CaveFileInfo {
             cave_name: "".to_string(),
             first_start_absolute_elevation: "0.0".to_string(),
-            geo_coding: "".to_string(),
-            //list_annotation: "".to_string(),
+            geo_coding: None,
+            annotations: Vec::new(),
             //data: "".to_string(),
             unit: "m".to_string(),
             use_magnetic_azimuth: "true".to_string(),
@@ -226,241 +803,1088 @@ impl Default for CaveFileInfo {
             // carto_selection: "".to_string(),
             // carto_ellipse: "".to_string(),
             // carto_spline: "".to_string(),
-            // layers: "".to_string(),
-            // carto_overlay: "".to_string(),
-            // carto_linked_surface: "".to_string(),
+            layers: vec![LayerList::new("Overlay"), LayerList::new("Default")],
+            carto_overlay: Vec::new(),
+            carto_linked_surface: Vec::new(),
+            unknown: Vec::new(),
         }
     }
 }
-fn opt_str(input: Option<String>) -> String {
-    match input {
-        Some(s) => s,
-        None => String::new(),
+// Writes xml the way Ariane does: every element on its own line and no indentation
+struct Emitter<W: Write> {
+    output: BufWriter<W>,
+}
+
+impl<W: Write> Emitter<W> {
+    fn start(&mut self, name: &str) -> std::io::Result<()> {
+        self.output.write_all(b"\n<")?;
+        self.output.write_all(name.as_bytes())?;
+        self.output.write_all(b">")
+    }
+
+    fn end(&mut self, name: &str) -> std::io::Result<()> {
+        self.output.write_all(b"\n</")?;
+        self.output.write_all(name.as_bytes())?;
+        self.output.write_all(b">")
+    }
+
+    fn empty(&mut self, name: &str) -> std::io::Result<()> {
+        self.output.write_all(b"\n<")?;
+        self.output.write_all(name.as_bytes())?;
+        self.output.write_all(b"/>")
+    }
+
+    fn element(&mut self, name: &str, val: &str) -> std::io::Result<()> {
+        self.start(name)?;
+        self.escaped(val)?;
+        self.output.write_all(b"</")?;
+        self.output.write_all(name.as_bytes())?;
+        self.output.write_all(b">")
+    }
+
+    fn opt_element(&mut self, name: &str, val: &Option<String>) -> std::io::Result<()> {
+        self.element(name, val.as_deref().unwrap_or_default())
+    }
+
+    fn escaped(&mut self, val: &str) -> std::io::Result<()> {
+        let bytes = val.as_bytes();
+        let mut start = 0;
+        for (i, b) in bytes.iter().enumerate() {
+            let entity: &[u8] = match b {
+                b'&' => b"&amp;",
+                b'<' => b"&lt;",
+                b'>' => b"&gt;",
+                _ => continue,
+            };
+            self.output.write_all(&bytes[start..i])?;
+            self.output.write_all(entity)?;
+            start = i + 1;
+        }
+        self.output.write_all(&bytes[start..])
+    }
+
+    fn annotations(&mut self, annotations: &[Annotation]) -> std::io::Result<()> {
+        if annotations.is_empty() {
+            return self.empty("ListAnnotation");
+        }
+        self.start("ListAnnotation")?;
+        for annotation in annotations {
+            if annotation.fields.is_empty() {
+                self.empty(&annotation.tag)?;
+                continue;
+            }
+            self.start(&annotation.tag)?;
+            for field in &annotation.fields {
+                self.element(&field.name, &field.value)?;
+            }
+            self.end(&annotation.tag)?;
+        }
+        self.end("ListAnnotation")
+    }
+
+    fn layers(&mut self, layers: &[LayerList]) -> std::io::Result<()> {
+        if layers.is_empty() {
+            return self.empty("Layers");
+        }
+        self.start("Layers")?;
+        for layer in layers {
+            self.start("layerList")?;
+            self.element("constant", &layer.constant)?;
+            self.element("locked", &layer.locked)?;
+            self.element("name", &layer.name)?;
+            let style = &layer.style;
+            self.start("style")?;
+            self.element("dashScale", &style.dash_scale)?;
+            self.element("fillColorString", &style.fill_color_string)?;
+            self.element("lineType", &style.line_type)?;
+            self.element("lineTypeScale", &style.line_type_scale)?;
+            self.element("opacity", &style.opacity)?;
+            self.element("sizeMode", &style.size_mode)?;
+            self.element("strokeColorString", &style.stroke_color_string)?;
+            self.element("strokeThickness", &style.stroke_thickness)?;
+            self.end("style")?;
+            self.element("visible", &layer.visible)?;
+            self.end("layerList")?;
+        }
+        self.end("Layers")
+    }
+
+    fn overlays(&mut self, overlays: &[CartoOverlay]) -> std::io::Result<()> {
+        if overlays.is_empty() {
+            return self.empty("CartoOverlay");
+        }
+        self.start("CartoOverlay")?;
+        for overlay in overlays {
+            self.start("overlay")?;
+            self.element("fileName", &overlay.file_name)?;
+            self.element("x", &overlay.x)?;
+            self.element("y", &overlay.y)?;
+            self.element("scale", &overlay.scale)?;
+            self.element("rotation", &overlay.rotation)?;
+            self.element("opacity", &overlay.opacity)?;
+            self.element("visible", &overlay.visible)?;
+            self.end("overlay")?;
+        }
+        self.end("CartoOverlay")
+    }
+
+    fn surfaces(&mut self, surfaces: &[CartoLinkedSurface]) -> std::io::Result<()> {
+        if surfaces.is_empty() {
+            return self.empty("CartoLinkedSurface");
+        }
+        self.start("CartoLinkedSurface")?;
+        for surface in surfaces {
+            self.start("surface")?;
+            self.element("fileName", &surface.file_name)?;
+            self.element("name", &surface.name)?;
+            self.element("opacity", &surface.opacity)?;
+            self.element("visible", &surface.visible)?;
+            self.end("surface")?;
+        }
+        self.end("CartoLinkedSurface")
+    }
+
+    fn unknown(&mut self, unknown: &[UnknownElement], after: &str) -> std::io::Result<()> {
+        for element in unknown.iter().filter(|u| u.after == after) {
+            self.output.write_all(b"\n")?;
+            self.output.write_all(element.xml.as_bytes())?;
+        }
+        Ok(())
     }
 }
 
-fn write_element<W: std::io::Write>(
-    writer: &mut EventWriter<W>,
-    name: &str,
-    val: &str,
-) -> std::result::Result<(), xml::writer::Error> {
-    writer.write(XmlEvent::start_element(name))?;
-    let a = xml::escape::escape_str_pcdata(val);
-    if val.contains('>') {
-        writer.write(XmlEvent::characters(&a.replace('>', "&gt;")))?;
-    } else {
-        writer.write(XmlEvent::characters(&a))?;
+// An element of a small piece of xml, to be mapped onto the model
+struct Node {
+    name: Vec<u8>,
+    text: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(name: &[u8]) -> Node {
+        Node {
+            name: name.to_vec(),
+            text: String::new(),
+            children: Vec::new(),
+        }
     }
-    writer.write(XmlEvent::end_element())?;
-    Ok(())
+
+    // Attributes are not kept
+    fn parse(xml: &str) -> Option<Node> {
+        let mut reader = Reader::from_str(xml);
+        reader.trim_text(true);
+        let mut open: Vec<Node> = Vec::new();
+        loop {
+            let node = match reader.read_event().ok()? {
+                Event::Start(e) => {
+                    open.push(Node::new(e.name().as_ref()));
+                    continue;
+                }
+                Event::Text(e) => {
+                    open.last_mut()?.text = e.unescape().ok()?.into_owned();
+                    continue;
+                }
+                Event::Empty(e) => Node::new(e.name().as_ref()),
+                Event::End(_) => open.pop()?,
+                Event::Eof => return None,
+                _ => continue,
+            };
+            match open.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => return Some(node),
+            }
+        }
+    }
+}
+
+// The element as the writer would write it
+fn written(write: impl FnOnce(&mut Emitter<Vec<u8>>) -> std::io::Result<()>) -> String {
+    let mut w = Emitter {
+        output: BufWriter::new(Vec::new()),
+    };
+    // Writing to memory can not fail
+    write(&mut w).unwrap();
+    let xml = w.output.into_inner().unwrap();
+    String::from_utf8_lossy(&xml[1..]).into_owned()
+}
+
+// The layers in a Layers element read raw, None if writing them would not give it back
+fn parse_layers(xml: &str) -> Option<Vec<LayerList>> {
+    let layers: Vec<LayerList> = Node::parse(xml)?
+        .children
+        .into_iter()
+        .map(|node| {
+            let mut layer = LayerList::default();
+            for child in node.children {
+                if child.name == b"style" {
+                    for field in child.children {
+                        layer.style.update(&field.name, field.text);
+                    }
+                } else {
+                    layer.update(&child.name, child.text);
+                }
+            }
+            layer
+        })
+        .collect();
+    Some(layers).filter(|layers| written(|w| w.layers(layers)) == xml)
+}
+
+// The annotations in a ListAnnotation element read raw, None if writing them would not give it
+// back
+fn parse_annotations(xml: &str) -> Option<Vec<Annotation>> {
+    let annotations: Vec<Annotation> = Node::parse(xml)?
+        .children
+        .into_iter()
+        .map(|node| Annotation {
+            tag: String::from_utf8_lossy(&node.name).into_owned(),
+            fields: node
+                .children
+                .into_iter()
+                .map(|field| AnnotationField {
+                    name: String::from_utf8_lossy(&field.name).into_owned(),
+                    value: field.text,
+                })
+                .collect(),
+        })
+        .collect();
+    Some(annotations).filter(|annotations| written(|w| w.annotations(annotations)) == xml)
+}
+
+// The overlays in a CartoOverlay element read raw, None if writing them would not give it back
+fn parse_overlays(xml: &str) -> Option<Vec<CartoOverlay>> {
+    let overlays: Vec<CartoOverlay> = Node::parse(xml)?
+        .children
+        .into_iter()
+        .map(|node| {
+            let mut overlay = CartoOverlay::default();
+            for field in node.children {
+                overlay.update(&field.name, field.text);
+            }
+            overlay
+        })
+        .collect();
+    Some(overlays).filter(|overlays| written(|w| w.overlays(overlays)) == xml)
+}
+
+// The surfaces in a CartoLinkedSurface element read raw, None if writing them would not give it
+// back
+fn parse_surfaces(xml: &str) -> Option<Vec<CartoLinkedSurface>> {
+    let surfaces: Vec<CartoLinkedSurface> = Node::parse(xml)?
+        .children
+        .into_iter()
+        .map(|node| {
+            let mut surface = CartoLinkedSurface::default();
+            for field in node.children {
+                surface.update(&field.name, field.text);
+            }
+            surface
+        })
+        .collect();
+    Some(surfaces).filter(|surfaces| written(|w| w.surfaces(surfaces)) == xml)
 }
-fn write_element_fast<W: std::io::Write>(
-    writer: &mut EventWriter<W>,
-    name: &str,
-    val: &str,
-) -> std::result::Result<(), xml::writer::Error> {
-    writer.write(XmlEvent::start_element(name))?;
-    writer.write(XmlEvent::characters(&val))?;
-    writer.write(XmlEvent::end_element())?;
-    Ok(())
+
+// Whether `name` was read with contents the model does not keep, and is among the unknown
+// elements instead
+fn replaced(unknown: &[UnknownElement], name: &str) -> bool {
+    unknown.iter().any(|u| u.name() == name)
 }
 
-pub fn write_cavefile<W: std::io::Write, I: IntoIterator<Item = SurveyData>>(
+pub fn write_cavefile<W: Write, I: IntoIterator<Item = SurveyData>>(
     output: W,
     survey_data: I,
     info: CaveFileInfo,
-) -> std::result::Result<(), xml::writer::Error> {
-    let mut config = EmitterConfig::new()
-        .perform_indent(true)
-        .indent_string("")
-        .pad_self_closing(false);
-    config.perform_escaping = false;
-
-    let mut writer = config.create_writer(output);
-
-    let _ = writer.write(XmlEvent::StartDocument {
-        version: XmlVersion::Version10,
-        encoding: Some("UTF-8"),
-        standalone: Some(true),
-    });
-
-    writer.write(XmlEvent::start_element("CaveFile"))?;
-    write_element(&mut writer, "caveName", &info.cave_name)?;
-    write_element(
-        &mut writer,
+) -> std::io::Result<()> {
+    let mut w = Emitter {
+        output: BufWriter::with_capacity(64 * 1024, output),
+    };
+
+    w.output
+        .write_all(br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#)?;
+    w.start("CaveFile")?;
+    w.unknown(&info.unknown, "")?;
+    w.element("caveName", &info.cave_name)?;
+    w.unknown(&info.unknown, "caveName")?;
+    w.element(
         "firstStartAbsoluteElevation",
         &info.first_start_absolute_elevation,
     )?;
-    write_element(&mut writer, "geoCoding", &info.geo_coding)?;
-    writer.write(XmlEvent::start_element("ListAnnotation"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("Data"))?;
-    for srvd in survey_data {
-        writer.write(XmlEvent::start_element("SRVD"))?;
-        write_element_fast(&mut writer, "AZ", &srvd.azimuth)?;
-        write_element_fast(&mut writer, "CID", &srvd.closure_to_id.to_string())?;
-        write_element_fast(&mut writer, "CL", &srvd.color)?;
-        write_element(&mut writer, "CM", opt_str(srvd.comment).as_str())?;
-        write_element_fast(&mut writer, "DT", &srvd.date)?;
-        write_element_fast(&mut writer, "DP", &srvd.depth)?;
-        write_element_fast(&mut writer, "DPI", &srvd.depth_in)?;
-        write_element(&mut writer, "D", &srvd.down)?;
-        write_element(&mut writer, "EXC", &srvd.excluded)?;
-        write_element(&mut writer, "EX", opt_str(srvd.explorer).as_str())?;
-        write_element_fast(&mut writer, "FRID", &srvd.from_id.to_string())?;
-        write_element_fast(&mut writer, "ID", &srvd.id.to_string())?;
-        write_element_fast(&mut writer, "INC", &srvd.inclination)?;
-        write_element_fast(&mut writer, "LT", &srvd.latitude)?;
-        write_element_fast(&mut writer, "L", &srvd.left)?;
-        write_element_fast(&mut writer, "LG", &srvd.length)?;
-        write_element_fast(&mut writer, "LK", &srvd.locked)?;
-        write_element_fast(&mut writer, "LGT", &srvd.longitude)?;
-        write_element(&mut writer, "NM", opt_str(srvd.name).as_str())?;
-        write_element_fast(&mut writer, "PRTY", &srvd.profile_type)?;
-        write_element_fast(&mut writer, "R", &srvd.right)?;
-        write_element(&mut writer, "SC", opt_str(srvd.section).as_str())?;
-        writer.write(XmlEvent::start_element("SH"))?;
-        write_element_fast(&mut writer, "HPRA", &srvd.shape.has_profile_azimut)?;
-        write_element_fast(&mut writer, "HPRT", &srvd.shape.has_profile_tilt)?;
-        write_element_fast(&mut writer, "PRAZ", &srvd.shape.profile_azimut)?;
-        write_element_fast(&mut writer, "PRT", &srvd.shape.profile_tilt)?;
-        writer.write(XmlEvent::start_element("RC"))?;
-        for rv in &srvd.shape.radius_collection {
-            writer.write(XmlEvent::start_element("RV"))?;
-            write_element_fast(&mut writer, "ag", &rv.angle)?;
-            write_element_fast(&mut writer, "lg", &rv.length)?;
-            write_element_fast(&mut writer, "tc", &rv.tension_corridor)?;
-            write_element_fast(&mut writer, "tp", &rv.tension_profile)?;
-            writer.write(XmlEvent::end_element())?;
-        }
-        writer.write(XmlEvent::end_element())?;
-        writer.write(XmlEvent::end_element())?;
-        write_element_fast(&mut writer, "TY", &srvd.station_type)?;
-        write_element_fast(&mut writer, "U", &srvd.up)?;
-        writer.write(XmlEvent::end_element())?;
-    }
-    writer.write(XmlEvent::end_element())?;
-    write_element(&mut writer, "unit", &info.unit)?;
-    write_element(
-        &mut writer,
-        "useMagneticAzimuth",
-        &info.use_magnetic_azimuth,
-    )?;
-    writer.write(XmlEvent::start_element("Constraints"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoLine"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoPage"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoRectangle"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoSelection"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoEllipse"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoSpline"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("Layers"))?;
-    for layer in [LayerList::new("Overlay"), LayerList::new("Default")].iter() {
-        writer.write(XmlEvent::start_element("layerList"))?;
-        write_element_fast(&mut writer, "constant", &layer.constant)?;
-        write_element_fast(&mut writer, "locked", &layer.locked)?;
-        write_element_fast(&mut writer, "name", &layer.name)?;
-        let style = &layer.style;
-        writer.write(XmlEvent::start_element("style"))?;
-        write_element_fast(&mut writer, "dashScale", &style.dash_scale)?;
-        write_element_fast(&mut writer, "fillColorString", &style.fill_color_string)?;
-        write_element_fast(&mut writer, "lineType", &style.line_type)?;
-        write_element_fast(&mut writer, "lineTypeScale", &style.line_type_scale)?;
-        write_element_fast(&mut writer, "opacity", &style.opacity)?;
-        write_element_fast(&mut writer, "sizeMode", &style.size_mode)?;
-        write_element_fast(&mut writer, "strokeColorString", &style.stroke_color_string)?;
-        write_element_fast(&mut writer, "strokeThickness", &style.stroke_thickness)?;
-        writer.write(XmlEvent::end_element())?;
-        write_element_fast(&mut writer, "visible", &layer.visible)?;
-        writer.write(XmlEvent::end_element())?;
-    }
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoOverlay"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::start_element("CartoLinkedSurface"))?;
-    writer.write(XmlEvent::end_element())?;
-    writer.write(XmlEvent::end_element())?;
-    Ok(())
+    w.unknown(&info.unknown, "firstStartAbsoluteElevation")?;
+    if let Some(geo_coding) = &info.geo_coding {
+        w.element("geoCoding", geo_coding)?;
+    }
+    w.unknown(&info.unknown, "geoCoding")?;
+    if !replaced(&info.unknown, "ListAnnotation") {
+        w.annotations(&info.annotations)?;
+    }
+    w.unknown(&info.unknown, "ListAnnotation")?;
+    let mut survey_data = survey_data.into_iter().peekable();
+    if survey_data.peek().is_none() {
+        w.empty("Data")?;
+    } else {
+        w.start("Data")?;
+    }
+    while let Some(srvd) = survey_data.next() {
+        w.start("SRVD")?;
+        w.unknown(&srvd.unknown, "")?;
+        w.element("AZ", &srvd.azimuth)?;
+        w.unknown(&srvd.unknown, "AZ")?;
+        w.element("CID", &srvd.closure_to_id.to_string())?;
+        w.unknown(&srvd.unknown, "CID")?;
+        w.element("CL", &srvd.color)?;
+        w.unknown(&srvd.unknown, "CL")?;
+        w.opt_element("CM", &srvd.comment)?;
+        w.unknown(&srvd.unknown, "CM")?;
+        w.element("DT", &srvd.date)?;
+        w.unknown(&srvd.unknown, "DT")?;
+        w.element("DP", &srvd.depth)?;
+        w.unknown(&srvd.unknown, "DP")?;
+        w.element("DPI", &srvd.depth_in)?;
+        w.unknown(&srvd.unknown, "DPI")?;
+        w.element("D", &srvd.down)?;
+        w.unknown(&srvd.unknown, "D")?;
+        w.element("EXC", &srvd.excluded)?;
+        w.unknown(&srvd.unknown, "EXC")?;
+        w.opt_element("EX", &srvd.explorer)?;
+        w.unknown(&srvd.unknown, "EX")?;
+        w.element("FRID", &srvd.from_id.to_string())?;
+        w.unknown(&srvd.unknown, "FRID")?;
+        w.element("ID", &srvd.id.to_string())?;
+        w.unknown(&srvd.unknown, "ID")?;
+        w.element("INC", &srvd.inclination)?;
+        w.unknown(&srvd.unknown, "INC")?;
+        w.element("LT", &srvd.latitude)?;
+        w.unknown(&srvd.unknown, "LT")?;
+        w.element("L", &srvd.left)?;
+        w.unknown(&srvd.unknown, "L")?;
+        w.element("LG", &srvd.length)?;
+        w.unknown(&srvd.unknown, "LG")?;
+        w.element("LK", &srvd.locked)?;
+        w.unknown(&srvd.unknown, "LK")?;
+        w.element("LGT", &srvd.longitude)?;
+        w.unknown(&srvd.unknown, "LGT")?;
+        w.opt_element("NM", &srvd.name)?;
+        w.unknown(&srvd.unknown, "NM")?;
+        w.element("PRTY", &srvd.profile_type)?;
+        w.unknown(&srvd.unknown, "PRTY")?;
+        w.element("R", &srvd.right)?;
+        w.unknown(&srvd.unknown, "R")?;
+        w.opt_element("SC", &srvd.section)?;
+        w.unknown(&srvd.unknown, "SC")?;
+        w.start("SH")?;
+        w.unknown(&srvd.unknown, "SH/")?;
+        w.element("HPRA", &srvd.shape.has_profile_azimut)?;
+        w.unknown(&srvd.unknown, "SH/HPRA")?;
+        w.element("HPRT", &srvd.shape.has_profile_tilt)?;
+        w.unknown(&srvd.unknown, "SH/HPRT")?;
+        w.element("PRAZ", &srvd.shape.profile_azimut)?;
+        w.unknown(&srvd.unknown, "SH/PRAZ")?;
+        w.element("PRT", &srvd.shape.profile_tilt)?;
+        w.unknown(&srvd.unknown, "SH/PRT")?;
+        let in_rc = |u: &UnknownElement| u.after.starts_with("SH/RC/");
+        if srvd.shape.radius_collection.is_empty() && !srvd.unknown.iter().any(in_rc) {
+            w.empty("RC")?;
+        } else {
+            w.start("RC")?;
+            w.unknown(&srvd.unknown, "SH/RC/")?;
+            for (i, rv) in srvd.shape.radius_collection.iter().enumerate() {
+                w.start("RV")?;
+                if srvd.unknown.is_empty() {
+                    w.element("ag", &rv.angle)?;
+                    w.element("lg", &rv.length)?;
+                    w.element("tc", &rv.tension_corridor)?;
+                    w.element("tp", &rv.tension_profile)?;
+                    w.end("RV")?;
+                    continue;
+                }
+                let rv_path = format!("SH/RC/RV[{}]", i);
+                w.unknown(&srvd.unknown, &format!("{}/", rv_path))?;
+                for (name, val) in [
+                    ("ag", &rv.angle),
+                    ("lg", &rv.length),
+                    ("tc", &rv.tension_corridor),
+                    ("tp", &rv.tension_profile),
+                ] {
+                    w.element(name, val)?;
+                    w.unknown(&srvd.unknown, &format!("{}/{}", rv_path, name))?;
+                }
+                w.end("RV")?;
+                w.unknown(&srvd.unknown, &rv_path)?;
+            }
+            w.end("RC")?;
+        }
+        w.unknown(&srvd.unknown, "SH/RC")?;
+        w.end("SH")?;
+        w.unknown(&srvd.unknown, "SH")?;
+        w.element("TY", &srvd.station_type)?;
+        w.unknown(&srvd.unknown, "TY")?;
+        w.element("U", &srvd.up)?;
+        w.unknown(&srvd.unknown, "U")?;
+        w.end("SRVD")?;
+        if survey_data.peek().is_none() {
+            w.end("Data")?;
+        }
+    }
+    w.unknown(&info.unknown, "Data")?;
+    w.element("unit", &info.unit)?;
+    w.unknown(&info.unknown, "unit")?;
+    w.element("useMagneticAzimuth", &info.use_magnetic_azimuth)?;
+    w.unknown(&info.unknown, "useMagneticAzimuth")?;
+    for name in [
+        "Constraints",
+        "CartoLine",
+        "CartoPage",
+        "CartoRectangle",
+        "CartoSelection",
+        "CartoEllipse",
+        "CartoSpline",
+    ] {
+        if !replaced(&info.unknown, name) {
+            w.empty(name)?;
+        }
+        w.unknown(&info.unknown, name)?;
+    }
+    if !replaced(&info.unknown, "Layers") {
+        w.layers(&info.layers)?;
+    }
+    w.unknown(&info.unknown, "Layers")?;
+    if !replaced(&info.unknown, "CartoOverlay") {
+        w.overlays(&info.carto_overlay)?;
+    }
+    w.unknown(&info.unknown, "CartoOverlay")?;
+    if !replaced(&info.unknown, "CartoLinkedSurface") {
+        w.surfaces(&info.carto_linked_surface)?;
+    }
+    w.unknown(&info.unknown, "CartoLinkedSurface")?;
+    w.end("CaveFile")?;
+    w.output.write_all(b"\n")?;
+    w.output.flush()
 }
 
 //https://docs.rs/quick-xml/latest/quick_xml/de/fn.from_reader.html#
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
 pub struct CaveFile {
     pub info: CaveFileInfo,
     pub data: Vec<SurveyData>,
 }
 
-pub fn read_cavefile<R: std::io::BufRead>(input: R) -> CaveFile {
-    use quick_xml::events::Event;
-    use quick_xml::reader::Reader;
-    let mut reader = Reader::from_reader(input);
-    reader.trim_text(true);
+impl CaveFile {
+    /// Elements that were not understood, with the id of the station they were found in
+    pub fn unknown_elements(&self) -> impl Iterator<Item = (Option<i32>, &UnknownElement)> {
+        self.info.unknown.iter().map(|u| (None, u)).chain(
+            self.data
+                .iter()
+                .flat_map(|srvd| srvd.unknown.iter().map(move |u| (Some(srvd.id), u))),
+        )
+    }
+}
+
+fn xml_error<R>(reader: &Reader<R>, error: quick_xml::Error) -> Error {
+    Error::Xml {
+        position: reader.buffer_position(),
+        error,
+    }
+}
+
+// Reads the rest of an element whose start tag was just read, one element per line like Ariane does
+fn read_unknown_element<R: BufRead>(
+    reader: &mut Reader<R>,
+    start: &BytesStart,
+    empty: bool,
+) -> Result<String> {
+    let mut raw = quick_xml::Writer::new(Vec::new());
+    if empty {
+        raw.write_event(Event::Empty(start.borrow()))
+            .map_err(|e| xml_error(reader, e))?;
+        return Ok(String::from_utf8_lossy(&raw.into_inner()).into_owned());
+    }
+    raw.write_event(Event::Start(start.borrow()))
+        .map_err(|e| xml_error(reader, e))?;
 
     let mut buf = Vec::new();
-    let mut is_srvd = false;
-    let mut current_tag = Vec::new();
+    let mut depth = 1;
+    // 0: start tag, 1: text, 2: other markup
+    let mut previous = 0;
+    while depth > 0 {
+        let event = match reader.read_event_into(&mut buf) {
+            Err(e) => return Err(xml_error(reader, e)),
+            Ok(Event::Eof) => break,
+            Ok(event) => event,
+        };
+        match event {
+            Event::Text(_) | Event::CData(_) => previous = 1,
+            Event::End(_) => {
+                depth -= 1;
+                if previous == 2 {
+                    raw.get_mut().push(b'\n');
+                }
+                previous = 2;
+            }
+            _ => {
+                if let Event::Start(_) = event {
+                    depth += 1;
+                }
+                if previous != 1 {
+                    raw.get_mut().push(b'\n');
+                }
+                previous = if let Event::Start(_) = event { 0 } else { 2 };
+            }
+        }
+        raw.write_event(event).map_err(|e| xml_error(reader, e))?;
+        buf.clear();
+    }
+    Ok(String::from_utf8_lossy(&raw.into_inner()).into_owned())
+}
 
-    let mut cave = CaveFile {
-        data: Vec::new(),
-        info: CaveFileInfo::default(),
-    };
+// Whether the xml at `offset` is the first element after Data or the end of CaveFile
+fn followed_by_trailer<R: Read + Seek>(input: &mut R, offset: u64) -> std::io::Result<bool> {
+    let mut next = Vec::new();
+    input.seek(SeekFrom::Start(offset))?;
+    input.take(256).read_to_end(&mut next)?;
+    let next = next.trim_ascii_start();
+    if next.starts_with(b"</CaveFile") {
+        return Ok(true);
+    }
+    let mut after_data = CAVEFILE_ELEMENTS
+        .iter()
+        .skip_while(|e| **e != b"Data")
+        .skip(1);
+    Ok(next.starts_with(b"<")
+        && after_data.any(|e| {
+            next[1..].starts_with(e)
+                && matches!(
+                    next.get(e.len() + 1),
+                    Some(b'>' | b'/' | b' ' | b'\t' | b'\r' | b'\n')
+                )
+        }))
+}
 
-    let mut current_srvd = SurveyData::default();
+// Offset just after the end of the Data element, searching backwards from the end of the input.
+// A tag counts only where an element that comes after Data follows it, so one inside an unknown
+// element at the end of the file is passed over
+fn find_data_end<R: Read + Seek>(input: &mut R) -> std::io::Result<Option<u64>> {
+    const CHUNK: u64 = 64 * 1024;
+    const TAGS: [&[u8]; 2] = [b"</Data>", b"<Data/>"];
+    let len = input.seek(SeekFrom::End(0))?;
+    let mut chunk = Vec::new();
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        // Overlap with the previous chunk so a tag split between the two is still found
+        let read_end = (end + 6).min(len);
+        chunk.resize((read_end - start) as usize, 0);
+        input.seek(SeekFrom::Start(start))?;
+        input.read_exact(&mut chunk)?;
+        let found: Vec<u64> = chunk
+            .windows(7)
+            .enumerate()
+            .filter(|(_, w)| TAGS.contains(w))
+            .map(|(i, _)| start + i as u64 + 7)
+            .collect();
+        for offset in found.into_iter().rev() {
+            if followed_by_trailer(input, offset)? {
+                return Ok(Some(offset));
+            }
+        }
+        end = start;
+    }
+    Ok(None)
+}
 
-    let mut path: Vec<Vec<u8>> = Vec::new();
-    let mut path_string: Vec<String> = Vec::new(); //TODO: remove this if performance is an issue
+/// Reads a cave file one station at a time, keeping only the current station in memory
+///
+/// Elements before `Data` are available from [`CaveFileReader::info`] before the first
+/// station is read. Elements after it (`unit`, `useMagneticAzimuth`, ...) are available once
+/// the iterator is exhausted, unless the reader was created with
+/// [`CaveFileReader::new_seekable`].
+pub struct CaveFileReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    info: CaveFileInfo,
+    path: Vec<Vec<u8>>,
+    current_tag: Vec<u8>,
+    last: String,
+    in_data: bool,
+    has_trailer: bool,
+    done: bool,
+}
 
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(Event::Eof) => break,
-            Ok(Event::Start(e)) => {
-                path.push(e.name().as_ref().to_owned());
-                path_string.push(String::from_utf8_lossy(e.name().as_ref()).to_string());
-                //println!("path_string: {:?}\n", path_string);
-                current_tag = e.name().as_ref().to_owned();
-                if e.name().as_ref() == b"SRVD" {
-                    is_srvd = true;
+impl<R: BufRead> CaveFileReader<R> {
+    pub fn new(input: R) -> Result<Self> {
+        let mut reader = Self::with_path(input, Vec::new(), CaveFileInfo::default());
+        reader.read_outside_data()?;
+        Ok(reader)
+    }
+
+    fn with_path(input: R, path: Vec<Vec<u8>>, info: CaveFileInfo) -> Self {
+        let mut reader = Reader::from_reader(input);
+        reader.trim_text(true);
+        CaveFileReader {
+            reader,
+            buf: Vec::new(),
+            info,
+            path,
+            current_tag: Vec::new(),
+            last: String::new(),
+            in_data: false,
+            has_trailer: false,
+            done: false,
+        }
+    }
+
+    pub fn info(&self) -> &CaveFileInfo {
+        &self.info
+    }
+
+    pub fn into_info(self) -> CaveFileInfo {
+        self.info
+    }
+
+    // Reads everything outside of Data, stops when entering Data or at the end of the file
+    fn read_outside_data(&mut self) -> Result<()> {
+        loop {
+            let mut entered_data = false;
+            match self.reader.read_event_into(&mut self.buf) {
+                Err(e) => return Err(xml_error(&self.reader, e)),
+                Ok(Event::Eof) => {
+                    self.done = true;
+                    break;
+                }
+                Ok(Event::Start(e)) => {
+                    let name = e.name();
+                    let unmodeled = !CAVEFILE_ELEMENTS.contains(&name.as_ref())
+                        || UNMODELED_ELEMENTS.contains(&name.as_ref());
+                    if self.path.len() == 1 && unmodeled {
+                        let xml = read_unknown_element(&mut self.reader, &e.to_owned(), false)?;
+                        self.info.unknown.push(UnknownElement {
+                            after: self.last.clone(),
+                            xml,
+                        });
+                    } else if self.path.len() == 1
+                        && matches!(
+                            name.as_ref(),
+                            b"Layers" | b"ListAnnotation" | b"CartoOverlay" | b"CartoLinkedSurface"
+                        )
+                    {
+                        // Kept as it is if there is more in it than the model has fields for
+                        let xml = read_unknown_element(&mut self.reader, &e.to_owned(), false)?;
+                        let info = &mut self.info;
+                        let modeled = match name.as_ref() {
+                            b"Layers" => parse_layers(&xml).map(|layers| info.layers = layers),
+                            b"ListAnnotation" => {
+                                parse_annotations(&xml).map(|list| info.annotations = list)
+                            }
+                            b"CartoOverlay" => {
+                                parse_overlays(&xml).map(|list| info.carto_overlay = list)
+                            }
+                            _ => parse_surfaces(&xml).map(|list| info.carto_linked_surface = list),
+                        };
+                        if modeled.is_some() {
+                            self.last = String::from_utf8_lossy(name.as_ref()).into_owned();
+                        } else {
+                            self.info.unknown.push(UnknownElement {
+                                after: self.last.clone(),
+                                xml,
+                            });
+                        }
+                    } else {
+                        if self.path.len() == 1 {
+                            self.last = String::from_utf8_lossy(name.as_ref()).into_owned();
+                        }
+                        self.path.push(name.as_ref().to_owned());
+                        self.current_tag = name.as_ref().to_owned();
+                        if self.path.len() == 2 && name.as_ref() == b"Data" {
+                            entered_data = true;
+                        } else if self.path.len() == 2 && name.as_ref() == b"geoCoding" {
+                            self.info.geo_coding = Some(String::new());
+                        }
+                    }
+                }
+                Ok(Event::Empty(e)) => {
+                    let name = e.name();
+                    if self.path.len() == 1 {
+                        if CAVEFILE_ELEMENTS.contains(&name.as_ref()) {
+                            self.last = String::from_utf8_lossy(name.as_ref()).into_owned();
+                            if name.as_ref() == b"geoCoding" {
+                                self.info.geo_coding = Some(String::new());
+                            } else if name.as_ref() == b"Layers" {
+                                self.info.layers.clear();
+                            } else if name.as_ref() == b"ListAnnotation" {
+                                self.info.annotations.clear();
+                            } else if name.as_ref() == b"CartoOverlay" {
+                                self.info.carto_overlay.clear();
+                            } else if name.as_ref() == b"CartoLinkedSurface" {
+                                self.info.carto_linked_surface.clear();
+                            } else if name.as_ref() == b"Data" && self.has_trailer {
+                                self.done = true;
+                                self.buf.clear();
+                                break;
+                            }
+                        } else {
+                            let xml = read_unknown_element(&mut self.reader, &e.to_owned(), true)?;
+                            self.info.unknown.push(UnknownElement {
+                                after: self.last.clone(),
+                                xml,
+                            });
+                        }
+                    }
+                }
+                Ok(Event::End(e)) => {
+                    // Only the trailer is read without checking the end tags match
+                    if self.path.len() == 1 && e.name().as_ref() != b"CaveFile" {
+                        let error = quick_xml::Error::EndEventMismatch {
+                            expected: "CaveFile".to_string(),
+                            found: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+                        };
+                        return Err(xml_error(&self.reader, error));
+                    }
+                    self.path.pop();
+                }
+                Ok(Event::Text(e)) => {
+                    let position = self.reader.buffer_position();
+                    let k = e
+                        .unescape()
+                        .map_err(|error| Error::Xml { position, error })?
+                        .into_owned();
+                    let tag = &self.current_tag;
+                    let info = &mut self.info;
+                    if tag == b"caveName" {
+                        info.cave_name = k;
+                    } else if tag == b"firstStartAbsoluteElevation" {
+                        info.first_start_absolute_elevation = k;
+                    } else if tag == b"geoCoding" {
+                        info.geo_coding = Some(k);
+                    } else if tag == b"unit" {
+                        info.unit = k;
+                    } else if tag == b"useMagneticAzimuth" {
+                        info.use_magnetic_azimuth = k;
+                    }
                 }
+                _ => (),
             }
-            Ok(Event::End(e)) => {
-                path.pop();
-                path_string.pop();
-                if e.name().as_ref() == b"SRVD" {
-                    let a = current_srvd.clone();
-                    cave.data.push(a);
-                    is_srvd = false;
-                    //TODO: avoid clearing the whole struct
-                    //There will be no text-events on empty tags..
-                    current_srvd = SurveyData::default();
+            self.buf.clear();
+            if entered_data {
+                self.in_data = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the next station, None when the end of Data is reached
+    fn read_station(&mut self) -> Result<Option<SurveyData>> {
+        let mut srvd = None;
+        let mut place = Place::default();
+        loop {
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Err(e) => return Err(xml_error(&self.reader, e)),
+                Ok(Event::Eof) => {
+                    self.in_data = false;
+                    self.done = true;
+                    return Ok(None);
                 }
+                Ok(event) => event,
+            };
+            let finished = station_event(
+                &mut self.reader,
+                &mut self.path,
+                &mut self.current_tag,
+                &mut srvd,
+                &mut place,
+                event,
+            )?;
+            self.buf.clear();
+            if finished {
+                self.in_data = self.path.len() > 1;
+                return Ok(srvd);
             }
-            Ok(Event::Text(e)) => {
-                //println!("S {:?}", path.last());
-                let k = e.unescape().unwrap().into_owned();
-                if is_srvd {
-                    current_srvd.update(&current_tag, &e);
-                } else if current_tag == b"caveName" {
-                    cave.info.cave_name = k;
-                } else if current_tag == b"firstStartAbsoluteElevation" {
-                    cave.info.first_start_absolute_elevation = k;
-                } else if current_tag == b"geoCoding" {
-                    cave.info.geo_coding = k;
-                } else if current_tag == b"unit" {
-                    cave.info.unit = k;
-                } else if current_tag == b"useMagneticAzimuth" {
-                    cave.info.use_magnetic_azimuth = k;
+        }
+    }
+
+    fn next_with<S>(
+        &mut self,
+        read_station: impl Fn(&mut Self) -> Result<Option<S>>,
+    ) -> Option<Result<S>> {
+        while !self.done {
+            let result = if self.in_data {
+                read_station(self)
+            } else if self.has_trailer {
+                self.done = true;
+                break;
+            } else {
+                self.read_outside_data().map(|_| None)
+            };
+            match result {
+                Ok(Some(srvd)) => return Some(Ok(srvd)),
+                Ok(None) => (),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
                 }
             }
+        }
+        None
+    }
+}
+
+impl<'a> CaveFileReader<&'a [u8]> {
+    /// Like [`Iterator::next`], but text that needs no unescaping is borrowed from the input
+    pub fn next_ref(&mut self) -> Option<Result<SurveyDataRef<'a>>> {
+        self.next_with(Self::read_station_ref)
+    }
+
+    fn read_station_ref(&mut self) -> Result<Option<SurveyDataRef<'a>>> {
+        let mut srvd = None;
+        let mut place = Place::default();
+        loop {
+            let event = match self.reader.read_event() {
+                Err(e) => return Err(xml_error(&self.reader, e)),
+                Ok(Event::Eof) => {
+                    self.in_data = false;
+                    self.done = true;
+                    return Ok(None);
+                }
+                Ok(event) => event,
+            };
+            if station_event(
+                &mut self.reader,
+                &mut self.path,
+                &mut self.current_tag,
+                &mut srvd,
+                &mut place,
+                event,
+            )? {
+                self.in_data = self.path.len() > 1;
+                return Ok(srvd);
+            }
+        }
+    }
+}
+
+trait Station<'x>: Default {
+    fn update(&mut self, tag: &[u8], val: Cow<'x, str>, position: usize) -> Result<()>;
+    /// Called for elements opening below the children of SRVD
+    fn start(&mut self, tag: &[u8]);
+    fn unknown(&mut self) -> &mut Vec<UnknownElement>;
+}
+
+const SHAPE_ELEMENTS: [&[u8]; 5] = [b"HPRA", b"HPRT", b"PRAZ", b"PRT", b"RC"];
+const RC_ELEMENTS: [&[u8]; 1] = [b"RV"];
+const RV_ELEMENTS: [&[u8]; 4] = [b"ag", b"lg", b"tc", b"tp"];
+
+// Where the reader is inside a SRVD, so unknown elements can be put back in the same place
+#[derive(Default)]
+struct Place {
+    // The last known element at each depth below SRVD, empty if there was none yet
+    last: [&'static [u8]; 4],
+    radius_vectors: usize,
+}
+
+impl Place {
+    // The elements known at `depth` below SRVD, None inside elements that only hold text
+    fn elements(&self, depth: usize) -> Option<&'static [&'static [u8]]> {
+        match depth {
+            0 => Some(&SRVD_ELEMENTS),
+            1 if self.last[0] == b"SH" => Some(&SHAPE_ELEMENTS),
+            2 if self.last[0] == b"SH" && self.last[1] == b"RC" => Some(&RC_ELEMENTS),
+            3 if self.last[0] == b"SH" && self.last[2] == b"RV" => Some(&RV_ELEMENTS),
+            _ => None,
+        }
+    }
+
+    fn enter(&mut self, depth: usize, name: &'static [u8]) {
+        self.last[depth] = name;
+        for last in self.last.iter_mut().skip(depth + 1) {
+            *last = b"";
+        }
+        match name {
+            b"RC" => self.radius_vectors = 0,
+            b"RV" => self.radius_vectors += 1,
             _ => (),
         }
-        buf.clear();
     }
-    cave
+
+    // See `UnknownElement::after`
+    fn after(&self, depth: usize) -> String {
+        let last = String::from_utf8_lossy(self.last[depth]);
+        match depth {
+            0 => last.into_owned(),
+            1 => format!("SH/{}", last),
+            2 if self.radius_vectors == 0 => "SH/RC/".to_string(),
+            2 => format!("SH/RC/RV[{}]", self.radius_vectors - 1),
+            _ => format!("SH/RC/RV[{}]/{}", self.radius_vectors - 1, last),
+        }
+    }
+}
+
+// Handles one event inside Data, returns true when a station or Data itself has ended.
+// This is the hot path, so it avoids allocating for the elements it knows
+fn station_event<'x, R: BufRead, S: Station<'x>>(
+    reader: &mut Reader<R>,
+    path: &mut Vec<Vec<u8>>,
+    current_tag: &mut Vec<u8>,
+    srvd: &mut Option<S>,
+    place: &mut Place,
+    event: Event<'x>,
+) -> Result<bool> {
+    // Depth below SRVD, and the known element `name` is if there are known elements there
+    let depth = path.len().saturating_sub(3);
+    let known = |place: &Place, name: &[u8]| {
+        place
+            .elements(depth)
+            .map(|elements| elements.iter().copied().find(|e| *e == name))
+    };
+    match event {
+        Event::Start(e) => {
+            let name = e.name();
+            match srvd.as_mut().filter(|_| path.len() >= 3) {
+                Some(current) if known(place, name.as_ref()) == Some(None) => {
+                    let xml = read_unknown_element(reader, &e.to_owned(), false)?;
+                    current.unknown().push(UnknownElement {
+                        after: place.after(depth),
+                        xml,
+                    });
+                }
+                Some(current) => {
+                    if let Some(Some(tag)) = known(place, name.as_ref()) {
+                        place.enter(depth, tag);
+                    }
+                    if depth > 0 {
+                        current.start(name.as_ref());
+                    }
+                    // Only the depth is used inside Data
+                    path.push(Vec::new());
+                    current_tag.clear();
+                    current_tag.extend_from_slice(name.as_ref());
+                }
+                None => {
+                    if path.len() == 2 && name.as_ref() == b"SRVD" {
+                        *srvd = Some(S::default());
+                        *place = Place::default();
+                    }
+                    path.push(Vec::new());
+                    current_tag.clear();
+                    current_tag.extend_from_slice(name.as_ref());
+                }
+            }
+        }
+        Event::Empty(e) => {
+            let name = e.name();
+            if let Some(current) = srvd.as_mut().filter(|_| path.len() >= 3) {
+                match known(place, name.as_ref()) {
+                    Some(Some(tag)) => place.enter(depth, tag),
+                    Some(None) => {
+                        let xml = read_unknown_element(reader, &e.to_owned(), true)?;
+                        current.unknown().push(UnknownElement {
+                            after: place.after(depth),
+                            xml,
+                        });
+                        return Ok(false);
+                    }
+                    None => (),
+                }
+                if depth > 0 {
+                    current.start(name.as_ref());
+                }
+            }
+        }
+        Event::End(e) => {
+            path.pop();
+            if (path.len() == 2 && e.name().as_ref() == b"SRVD") || path.len() == 1 {
+                return Ok(true);
+            }
+        }
+        Event::Text(e) => {
+            if let Some(current) = srvd.as_mut() {
+                let position = reader.buffer_position();
+                let val = e
+                    .unescape()
+                    .map_err(|error| Error::Xml { position, error })?;
+                current.update(current_tag, val, position)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(false)
+}
+
+impl<R: BufRead + Seek> CaveFileReader<R> {
+    /// Like [`CaveFileReader::new`], but reads the elements after `Data` first, so all of
+    /// [`CaveFileReader::info`] is available before the first station. If the end of `Data`
+    /// can not be found, they are read after the stations like with [`CaveFileReader::new`]
+    pub fn new_seekable(mut input: R) -> Result<Self> {
+        let mut info = CaveFileInfo::default();
+        let mut has_trailer = false;
+        if let Some(offset) = find_data_end(&mut input)? {
+            input.seek(SeekFrom::Start(offset))?;
+            let mut trailer = CaveFileReader::with_path(
+                &mut input,
+                vec![b"CaveFile".to_vec()],
+                CaveFileInfo::default(),
+            );
+            trailer.last = "Data".to_string();
+            // The start tag of CaveFile is not part of what the trailer reader sees
+            trailer.reader.check_end_names(false);
+            // A trailer that does not parse did not start at the end of Data after all
+            if trailer.read_outside_data().is_ok() {
+                info = trailer.into_info();
+                has_trailer = true;
+            }
+        }
+        input.seek(SeekFrom::Start(0))?;
+        let mut reader = Self::with_path(input, Vec::new(), info);
+        reader.has_trailer = has_trailer;
+        reader.read_outside_data()?;
+        // Unknown elements from the header were added after the ones from the trailer
+        reader.info.unknown.sort_by_key(|u| {
+            CAVEFILE_ELEMENTS
+                .iter()
+                .position(|e| *e == u.after.as_bytes())
+                .map_or(0, |p| p + 1)
+        });
+        Ok(reader)
+    }
+}
+
+impl<R: BufRead> Iterator for CaveFileReader<R> {
+    type Item = Result<SurveyData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with(Self::read_station)
+    }
+}
+
+pub fn read_cavefile<R: BufRead>(input: R) -> CaveFile {
+    try_read_cavefile(input).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_read_cavefile<R: BufRead>(input: R) -> Result<CaveFile> {
+    let mut reader = CaveFileReader::new(input)?;
+    let data = reader.by_ref().collect::<Result<Vec<_>>>()?;
+    Ok(CaveFile {
+        info: reader.into_info(),
+        data,
+    })
+}
+
+/// A cave file whose stations borrow their text from the input buffer
+#[derive(Debug)]
+pub struct CaveFileRef<'a> {
+    pub info: CaveFileInfo,
+    pub data: Vec<SurveyDataRef<'a>>,
+}
+
+/// Reads a cave file from memory (a whole file or a memory map) without copying station text
+pub fn read_cavefile_ref(input: &[u8]) -> Result<CaveFileRef<'_>> {
+    let mut reader = CaveFileReader::new(input)?;
+    let mut data = Vec::new();
+    while let Some(srvd) = reader.next_ref() {
+        data.push(srvd?);
+    }
+    Ok(CaveFileRef {
+        info: reader.into_info(),
+        data,
+    })
 }
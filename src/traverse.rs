@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::error::Result;
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::units::Unit;
+use crate::utils::parse_double;
+
+// Mean earth radius, used to place START stations relative to each other
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Position of a station in the unit of the file. `x` is east and `y` is north of the first
+/// START station, `z` is up, with 0 at the surface the depths are measured from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl std::ops::Add for Point {
+    type Output = Point;
+
+    fn add(self, other: Point) -> Point {
+        Point {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+impl std::ops::Sub for Point {
+    type Output = Point;
+
+    fn sub(self, other: Point) -> Point {
+        Point {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+impl Point {
+    pub fn length(&self) -> f64 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    pub fn horizontal_length(&self) -> f64 {
+        self.x.hypot(self.y)
+    }
+}
+
+/// Station positions computed by following the legs out from each START station
+#[derive(Debug, Clone, Default)]
+pub struct Traverse {
+    stations: Vec<(i32, Point)>,
+    starts: Vec<i32>,
+    index: HashMap<i32, usize>,
+    // Latitude and longitude of the first START, and the length of a degree of latitude
+    origin: (f64, f64),
+    degree: f64,
+}
+
+impl Traverse {
+    pub fn get(&self, id: i32) -> Option<Point> {
+        self.index.get(&id).map(|&i| self.stations[i].1)
+    }
+
+    /// The id of the START station `id` was reached from
+    pub fn start_of(&self, id: i32) -> Option<i32> {
+        self.index.get(&id).map(|&i| self.starts[i])
+    }
+
+    /// Latitude and longitude of a station, from its offset to the first START station
+    pub fn lat_lon(&self, id: i32) -> Option<(f64, f64)> {
+        let point = self.get(id)?;
+        let (latitude0, longitude0) = self.origin;
+        Some((
+            latitude0 + point.y / self.degree,
+            longitude0 + point.x / (self.degree * latitude0.to_radians().cos()),
+        ))
+    }
+
+    /// Stations in the order they were reached
+    pub fn iter(&self) -> impl Iterator<Item = (i32, Point)> + '_ {
+        self.stations.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    fn push(&mut self, id: i32, point: Point, start: i32) {
+        self.index.insert(id, self.stations.len());
+        self.stations.push((id, point));
+        self.starts.push(start);
+    }
+}
+
+/// The vector from the `from` station to `srvd`, with `correction` degrees added to the azimuth.
+/// The vertical part comes from the change in depth, the inclination is only used when the
+/// depth does not change
+pub fn leg_vector(from: &SurveyData, srvd: &SurveyData, correction: f64) -> Result<Point> {
+    let id = Some(srvd.id);
+    let length = parse_double(&srvd.length, id, "LG")?;
+    let azimuth = (parse_double(&srvd.azimuth, id, "AZ")? + correction).to_radians();
+    let rise =
+        parse_double(&from.depth, Some(from.id), "DP")? - parse_double(&srvd.depth, id, "DP")?;
+    let inclination = parse_double(&srvd.inclination, id, "INC")?.to_radians();
+    let (horizontal, rise) = if rise == 0.0 && inclination != 0.0 {
+        (length * inclination.cos(), length * inclination.sin())
+    } else {
+        ((length * length - rise * rise).max(0.0).sqrt(), rise)
+    };
+    Ok(Point {
+        x: horizontal * azimuth.sin(),
+        y: horizontal * azimuth.cos(),
+        z: rise,
+    })
+}
+
+/// Computes station positions without any azimuth correction
+pub fn traverse(cave: &CaveFile) -> Result<Traverse> {
+    traverse_with(cave, |_, _| Ok(0.0))
+}
+
+/// Computes station positions, adding `correction(leg, start)` degrees to the azimuth of each
+/// leg, where `start` is the START station the leg is connected to.
+/// Stations that can not be reached from a START station are left out.
+/// A CLOSURE row gets the position of its `from_id`
+pub fn traverse_with<F>(cave: &CaveFile, mut correction: F) -> Result<Traverse>
+where
+    F: FnMut(&SurveyData, &SurveyData) -> Result<f64>,
+{
+    let unit: Unit = cave.info.unit.parse()?;
+    let meters = Unit::Meters.factor(unit);
+    let mut children: HashMap<i32, Vec<&SurveyData>> = HashMap::new();
+    for srvd in &cave.data {
+        if srvd.station_type != "START" {
+            children.entry(srvd.from_id).or_default().push(srvd);
+        }
+    }
+
+    let mut result = Traverse {
+        degree: EARTH_RADIUS * PI / 180.0 * meters,
+        ..Default::default()
+    };
+    let mut origin = None;
+    let mut stack = Vec::new();
+    for start in cave.data.iter().filter(|s| s.station_type == "START") {
+        if result.index.contains_key(&start.id) {
+            continue;
+        }
+        let latitude = parse_double(&start.latitude, Some(start.id), "LT")?;
+        let longitude = parse_double(&start.longitude, Some(start.id), "LGT")?;
+        let (latitude0, longitude0) = *origin.get_or_insert((latitude, longitude));
+        result.origin = (latitude0, longitude0);
+        let degree = result.degree;
+        let position = Point {
+            x: (longitude - longitude0) * degree * latitude0.to_radians().cos(),
+            y: (latitude - latitude0) * degree,
+            z: -parse_double(&start.depth, Some(start.id), "DP")?,
+        };
+        result.push(start.id, position, start.id);
+
+        stack.push((start, position));
+        while let Some((from, position)) = stack.pop() {
+            let depth = stack.len();
+            for srvd in children.get(&from.id).into_iter().flatten() {
+                if result.index.contains_key(&srvd.id) {
+                    continue;
+                }
+                if srvd.station_type == "CLOSURE" {
+                    result.push(srvd.id, position, start.id);
+                    continue;
+                }
+                let next = position + leg_vector(from, srvd, correction(srvd, start)?)?;
+                result.push(srvd.id, next, start.id);
+                stack.push((srvd, next));
+            }
+            // So the branches are followed in file order
+            stack[depth..].reverse();
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::tmlu::{CaveFile, SurveyData};
+use crate::utils::{format_rounded, parse_double};
+
+const METERS_PER_FOOT: f64 = 0.3048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Meters,
+    Feet,
+}
+
+impl Unit {
+    /// The value used in `<unit>`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Unit::Meters => "m",
+            Unit::Feet => "ft",
+        }
+    }
+
+    fn in_meters(&self) -> f64 {
+        match self {
+            Unit::Meters => 1.0,
+            Unit::Feet => METERS_PER_FOOT,
+        }
+    }
+
+    /// Factor to multiply a length in `self` with to get it in `to`
+    pub fn factor(&self, to: Unit) -> f64 {
+        self.in_meters() / to.in_meters()
+    }
+}
+
+impl FromStr for Unit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Unit> {
+        match s.trim() {
+            "m" | "meters" => Ok(Unit::Meters),
+            "ft" | "feet" => Ok(Unit::Feet),
+            other => Err(Error::UnknownUnit(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn scale(value: &mut String, factor: f64, id: Option<i32>, tag: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        return Ok(());
+    }
+    *value = format_rounded(parse_double(value, id, tag)? * factor);
+    Ok(())
+}
+
+/// Rescales the lengths of one station, for converting a stream of stations
+pub fn convert_survey_data(srvd: &mut SurveyData, from: Unit, to: Unit) -> Result<()> {
+    if from == to {
+        return Ok(());
+    }
+    let factor = from.factor(to);
+    let id = Some(srvd.id);
+    scale(&mut srvd.length, factor, id, "LG")?;
+    scale(&mut srvd.depth, factor, id, "DP")?;
+    // -1.0 means the depth in was not measured
+    if srvd.depth_in.trim().parse::<f64>() != Ok(-1.0) {
+        scale(&mut srvd.depth_in, factor, id, "DPI")?;
+    }
+    scale(&mut srvd.left, factor, id, "L")?;
+    scale(&mut srvd.right, factor, id, "R")?;
+    scale(&mut srvd.up, factor, id, "U")?;
+    scale(&mut srvd.down, factor, id, "D")?;
+    for rv in &mut srvd.shape.radius_collection {
+        scale(&mut rv.length, factor, id, "lg")?;
+    }
+    Ok(())
+}
+
+/// Converts all lengths in `cave` to `unit` and updates `<unit>`
+pub fn convert_units(cave: &mut CaveFile, unit: Unit) -> Result<()> {
+    let from: Unit = cave.info.unit.parse()?;
+    if from == unit {
+        return Ok(());
+    }
+    // Works on copies so an invalid value leaves `cave` untouched
+    let mut elevation = cave.info.first_start_absolute_elevation.clone();
+    scale(
+        &mut elevation,
+        from.factor(unit),
+        None,
+        "firstStartAbsoluteElevation",
+    )?;
+    let mut data = cave.data.clone();
+    for srvd in &mut data {
+        convert_survey_data(srvd, from, unit)?;
+    }
+    cave.data = data;
+    cave.info.first_start_absolute_elevation = elevation;
+    cave.info.unit = unit.as_str().to_string();
+    Ok(())
+}
@@ -1,60 +1,211 @@
-use regex::Regex;
+use crate::error::{Error, Result};
 
-pub struct SplitExplorers {
-    pattern: Regex,
+/// Splits the `EX` field of a station, the way [`Team::parse`] does
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SplitExplorers;
+
+impl SplitExplorers {
+    /// The explorers and the surveyors, None if there are no names
+    pub fn split_explorers(&self, raw: &str) -> Option<(Vec<String>, Vec<String>)> {
+        let team = Team::parse(raw);
+        (!team.is_empty()).then_some((team.explorers, team.surveyors))
+    }
+
+    /// Like [`SplitExplorers::split_explorers`], with the names joined by `, `
+    pub fn split_explorers_string(&self, raw: &str) -> Option<(String, String)> {
+        self.split_explorers(raw)
+            .map(|(explorers, surveyors)| (explorers.join(", "), surveyors.join(", ")))
+    }
+}
+
+/// The section name, without the description Ariane appends to it
+pub fn section_name(section: &str) -> &str {
+    section
+        .split_once("<SectionDescription>")
+        .map_or(section, |(name, _)| name)
+        .trim()
+}
+
+/// Collapses runs of whitespace into one space
+pub fn clean_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The explorers and surveyors of a leg, from the `EX` field.
+/// Ariane writes `<Explorer>a, b</Explorer><Surveyor>c</Surveyor>`, but a missing tag or a
+/// plain comma separated list (taken as explorers) is read too
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Team {
+    pub explorers: Vec<String>,
+    pub surveyors: Vec<String>,
+    /// The field as it was read
+    pub raw: String,
 }
-impl Default for SplitExplorers {
-     fn default() -> Self {
-        let pattern = Regex::new(
-            r"<Explorer>(?<explorers>.*)</Explorer><Surveyor>(?<surveyors>.*)</Surveyor>",
+
+// The text after `<tag>`, up to its end tag or the next tag if it is missing
+fn tagged<'a>(raw: &'a str, tag: &str) -> Option<&'a str> {
+    let start = raw.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let rest = &raw[start..];
+    let end = rest
+        .find(&format!("</{}>", tag))
+        .or_else(|| rest.find('<'))
+        .unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+fn names(list: &str) -> Vec<String> {
+    list.split([',', ';'])
+        .map(clean_name)
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+impl Team {
+    pub fn parse(raw: &str) -> Team {
+        let (explorers, surveyors) = match (tagged(raw, "Explorer"), tagged(raw, "Surveyor")) {
+            (None, None) => (names(raw), Vec::new()),
+            (explorers, surveyors) => (
+                names(explorers.unwrap_or_default()),
+                names(surveyors.unwrap_or_default()),
+            ),
+        };
+        Team {
+            explorers,
+            surveyors,
+            raw: raw.to_string(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.explorers.is_empty() && self.surveyors.is_empty()
+    }
+
+    /// The `EX` value: `raw` if the names were not changed, otherwise the Ariane format
+    pub fn to_ariane(&self) -> String {
+        let read = Team::parse(&self.raw);
+        if read.explorers == self.explorers && read.surveyors == self.surveyors {
+            return self.raw.clone();
+        }
+        format!(
+            "<Explorer>{}</Explorer><Surveyor>{}</Surveyor>",
+            self.explorers.join(", "),
+            self.surveyors.join(", ")
         )
-        .unwrap();
-        Self { pattern }
     }
 }
-impl SplitExplorers {
-    #[allow(dead_code)]
-    pub fn split_explorers(&self, raw: &str) -> Option<(Vec<String>, Vec<String>)> {
-        if raw.is_empty() {
-            return None;
-        }
 
-        if let Some(cap) = &self.pattern.captures(raw) {
-            let explorers = &cap["explorers"];
-            let surveyors = &cap["surveyors"];
-            Some((
-                explorers
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .collect::<Vec<_>>(),
-                surveyors
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .collect::<Vec<_>>(),
-            ))
+impl std::fmt::Display for Team {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_ariane())
+    }
+}
+
+/// Formats a double the way Java's `Double.toString` does, which is what Ariane writes
+pub fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    let magnitude = value.abs();
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        let s = value.to_string();
+        if s.contains('.') {
+            s
         } else {
-            None
+            s + ".0"
+        }
+    } else {
+        let s = format!("{:e}", value);
+        let (mantissa, exponent) = s.split_once('e').unwrap();
+        if mantissa.contains('.') {
+            format!("{}E{}", mantissa, exponent)
+        } else {
+            format!("{}.0E{}", mantissa, exponent)
         }
     }
+}
 
-    pub fn split_explorers_string(&self, raw: &str) -> Option<(String, String)> {
-        self.pattern
-            .captures(raw)
-            .as_ref()
-            .map(|cap| (cap["explorers"].to_string(), cap["surveyors"].to_string()))
-    }
+// Computed values are rounded to this many decimals to hide floating point noise
+const DECIMALS: i32 = 6;
+
+/// Like [`format_double`], but rounded to six decimals
+pub(crate) fn format_rounded(value: f64) -> String {
+    let rounding = 10f64.powi(DECIMALS);
+    format_double((value * rounding).round() / rounding)
+}
+
+/// Parses one of the numeric string fields, `id` and `tag` are used in the error
+pub(crate) fn parse_double(value: &str, id: Option<i32>, tag: &str) -> Result<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| Error::InvalidField {
+            id,
+            tag: tag.to_string(),
+            value: value.to_string(),
+        })
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::SplitExplorers;
+    use super::{format_double, SplitExplorers, Team};
     #[test]
     fn test() {
         let a = "<Explorer>Carla Tortelli, Diane Chambers, Sam Malone</Explorer><Surveyor>Lilith Sternin, Sam Malone, Norm Peterson</Surveyor>".to_string();
-        let splitter = SplitExplorers::default();
+        let splitter = SplitExplorers;
         let (e, s) = splitter.split_explorers(&a).unwrap();
         assert_eq!(e, vec!["Carla Tortelli", "Diane Chambers", "Sam Malone"]);
         assert_eq!(s, vec!["Lilith Sternin", "Sam Malone", "Norm Peterson"]);
+        let (e, s) = splitter
+            .split_explorers("Carla Tortelli, Sam Malone")
+            .unwrap();
+        assert_eq!(e, vec!["Carla Tortelli", "Sam Malone"]);
+        assert!(s.is_empty());
+        assert_eq!(splitter.split_explorers(""), None);
+    }
+
+    #[test]
+    fn java_doubles() {
+        assert_eq!(format_double(0.0), "0.0");
+        assert_eq!(format_double(-0.0), "-0.0");
+        assert_eq!(format_double(10.0), "10.0");
+        assert_eq!(format_double(3.048), "3.048");
+        assert_eq!(format_double(-1.0), "-1.0");
+        assert_eq!(format_double(0.001), "0.001");
+        assert_eq!(format_double(0.0001), "1.0E-4");
+        assert_eq!(format_double(12345678.9), "1.23456789E7");
+        assert_eq!(format_double(1e7), "1.0E7");
+    }
+
+    #[test]
+    fn teams() {
+        let raw =
+            "<Explorer>Carla Tortelli, Sam Malone</Explorer><Surveyor>Norm Peterson</Surveyor>";
+        let mut team = Team::parse(raw);
+        assert_eq!(team.explorers, vec!["Carla Tortelli", "Sam Malone"]);
+        assert_eq!(team.surveyors, vec!["Norm Peterson"]);
+        assert_eq!(team.to_ariane(), raw);
+
+        team.surveyors.push("Sam Malone".to_string());
+        assert_eq!(
+            team.to_string(),
+            "<Explorer>Carla Tortelli, Sam Malone</Explorer><Surveyor>Norm Peterson, Sam Malone</Surveyor>"
+        );
+
+        let team = Team::parse(" Carla  Tortelli ,,Sam Malone ");
+        assert_eq!(team.explorers, vec!["Carla Tortelli", "Sam Malone"]);
+        assert!(team.surveyors.is_empty());
+        assert_eq!(team.to_ariane(), " Carla  Tortelli ,,Sam Malone ");
+
+        let team = Team::parse("<Surveyor> Norm Peterson </Surveyor>");
+        assert!(team.explorers.is_empty());
+        assert_eq!(team.surveyors, vec!["Norm Peterson"]);
+        let team = Team::parse("<Explorer>Diane Chambers<Surveyor>Norm Peterson");
+        assert_eq!(team.explorers, vec!["Diane Chambers"]);
+        assert_eq!(team.surveyors, vec!["Norm Peterson"]);
+        assert!(Team::parse("").is_empty());
     }
-}
\ No newline at end of file
+}
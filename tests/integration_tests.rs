@@ -31,7 +31,1321 @@ mod integration_tests {
     }
 
     #[test]
-    #[ignore = "Some files have missing geoCoding tags, some have empty, why?"]
+    pub fn overlays_there_and_back() {
+        let testfile = open_test_file("test1.tmlu");
+        let mut cave = tmlu_rs::tmlu::read_cavefile(testfile);
+        assert!(cave.info.carto_overlay.is_empty());
+        cave.info.carto_overlay.push(tmlu_rs::tmlu::CartoOverlay {
+            file_name: "overlays/plan & profile.png".to_string(),
+            x: "12.5".to_string(),
+            y: "-3.25".to_string(),
+            scale: "0.5".to_string(),
+            rotation: "12.0".to_string(),
+            opacity: "40.0".to_string(),
+            visible: "false".to_string(),
+        });
+        cave.info
+            .carto_linked_surface
+            .push(tmlu_rs::tmlu::CartoLinkedSurface {
+                file_name: "/data/dem.tif".to_string(),
+                name: "DEM".to_string(),
+                ..Default::default()
+            });
+
+        let mut output = std::io::Cursor::new(Vec::new());
+        tmlu_rs::tmlu::write_cavefile(&mut output, cave.data, cave.info).unwrap();
+        output.set_position(0);
+        let cave = tmlu_rs::tmlu::read_cavefile(output);
+
+        assert_eq!(cave.info.carto_overlay.len(), 1);
+        let overlay = &cave.info.carto_overlay[0];
+        assert_eq!(overlay.file_name, "overlays/plan & profile.png");
+        assert_eq!(overlay.x, "12.5");
+        assert_eq!(overlay.y, "-3.25");
+        assert_eq!(overlay.scale, "0.5");
+        assert_eq!(overlay.rotation, "12.0");
+        assert_eq!(overlay.opacity, "40.0");
+        assert_eq!(overlay.visible, "false");
+        assert_eq!(
+            overlay.resolve_path("/caves/fothulen.tmlu"),
+            path::Path::new("/caves/overlays/plan & profile.png")
+        );
+
+        assert_eq!(cave.info.carto_linked_surface.len(), 1);
+        let surface = &cave.info.carto_linked_surface[0];
+        assert_eq!(surface.name, "DEM");
+        assert_eq!(
+            surface.resolve_path("/caves/fothulen.tmlu"),
+            path::Path::new("/data/dem.tif")
+        );
+
+        // What the model has no place for keeps the element as it was
+        let original = std::fs::read_to_string(test_file("test1.tmlu"))
+            .unwrap()
+            .replace(
+                "<CartoOverlay/>",
+                "<CartoOverlay>\n<overlay>\n<fileName>a.png</fileName>\n<layer>2</layer>\n</overlay>\n</CartoOverlay>",
+            )
+            .replace(
+                "<CartoLinkedSurface/>",
+                "<CartoLinkedSurface>\n<surface>\n<fileName>dem.tif</fileName>\n<name>DEM</name>\n<opacity>100.0</opacity>\n<visible>true</visible>\n<crs>EPSG:25833</crs>\n</surface>\n</CartoLinkedSurface>",
+            );
+        let cave = tmlu_rs::tmlu::read_cavefile(original.as_bytes());
+        assert!(cave.info.carto_overlay.is_empty());
+        assert!(cave.info.carto_linked_surface.is_empty());
+        let names: Vec<_> = cave.info.unknown.iter().map(|u| u.name()).collect();
+        assert_eq!(names, vec!["CartoOverlay", "CartoLinkedSurface"]);
+        let mut output = Vec::new();
+        tmlu_rs::tmlu::write_cavefile(&mut output, cave.data, cave.info).unwrap();
+        assert_eq!(original, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    pub fn unknown_elements_there_and_back() {
+        let original = std::fs::read_to_string(test_file("test1.tmlu"))
+            .unwrap()
+            .replace(
+                "<ListAnnotation/>\n",
+                "<geoCoding></geoCoding>\n<ListAnnotation/>\n<future version=\"2\">\n<a>1 &amp; 2</a>\n<b/>\n</future>\n",
+            )
+            .replace("<LK>true</LK>\n", "<LK>true</LK>\n<XYZ>new</XYZ>\n")
+            .replace("<AZ>0.0</AZ>", "<NEW/>\n<AZ>0.0</AZ>")
+            .replace("<PRT>0.0</PRT>", "<PRT>0.0</PRT>\n<NEWSH>7</NEWSH>")
+            .replacen("<tc>1.0</tc>", "<tc>1.0</tc>\n<tw/>", 1)
+            .replace(
+                "<CartoLine/>",
+                "<CartoLine>\n<line>\n<x>1.0</x>\n</line>\n</CartoLine>",
+            );
+        let cave = tmlu_rs::tmlu::read_cavefile(original.as_bytes());
+
+        let unknown: Vec<_> = cave
+            .unknown_elements()
+            .map(|(id, u)| (id, u.name().to_string(), u.after.clone()))
+            .collect();
+        assert_eq!(
+            unknown,
+            vec![
+                (None, "future".to_string(), "ListAnnotation".to_string()),
+                (None, "CartoLine".to_string(), "Constraints".to_string()),
+                (Some(0), "NEW".to_string(), "".to_string()),
+                (Some(0), "XYZ".to_string(), "LK".to_string()),
+                (Some(0), "NEWSH".to_string(), "SH/PRT".to_string()),
+                (Some(0), "tw".to_string(), "SH/RC/RV[0]/tc".to_string()),
+            ]
+        );
+        assert_eq!(
+            cave.info.unknown[0].xml,
+            "<future version=\"2\">\n<a>1 &amp; 2</a>\n<b/>\n</future>"
+        );
+        let seekable = tmlu_rs::tmlu::CaveFileReader::new_seekable(std::io::Cursor::new(
+            original.replace("<CartoOverlay/>", "<CartoOverlay/>\n<last/>"),
+        ))
+        .unwrap();
+        let names: Vec<_> = seekable.info().unknown.iter().map(|u| u.name()).collect();
+        assert_eq!(names, vec!["future", "CartoLine", "last"]);
+        assert_eq!(cave.data[0].locked, "true");
+        assert_eq!(cave.data[0].azimuth, "0.0");
+
+        let mut output = Vec::new();
+        tmlu_rs::tmlu::write_cavefile(&mut output, cave.data, cave.info).unwrap();
+        assert_eq!(original, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    pub fn layers_and_annotations_there_and_back() {
+        use tmlu_rs::tmlu::{Annotation, AnnotationField};
+
+        let original = std::fs::read_to_string(test_file("test1.tmlu"))
+            .unwrap()
+            .replace(
+                "<ListAnnotation/>",
+                "<ListAnnotation>\n<annotation>\n<text>Sump &amp; duck</text>\n<x>1.5</x>\n</annotation>\n<annotation/>\n</ListAnnotation>",
+            )
+            .replacen("<opacity>100.0</opacity>", "<opacity>50.0</opacity>", 1);
+        let cave = tmlu_rs::tmlu::read_cavefile(original.as_bytes());
+        assert!(cave.info.unknown.is_empty());
+        let field = |name: &str, value: &str| AnnotationField {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        assert_eq!(
+            cave.info.annotations,
+            vec![
+                Annotation {
+                    tag: "annotation".to_string(),
+                    fields: vec![field("text", "Sump & duck"), field("x", "1.5")],
+                },
+                Annotation {
+                    tag: "annotation".to_string(),
+                    fields: vec![],
+                },
+            ]
+        );
+        let layers: Vec<_> = cave.info.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(layers, vec!["Overlay", "Default"]);
+        assert_eq!(cave.info.layers[0].style.opacity, "50.0");
+        assert_eq!(cave.info.layers[1].style.opacity, "100.0");
+
+        let mut output = Vec::new();
+        tmlu_rs::tmlu::write_cavefile(&mut output, cave.data, cave.info).unwrap();
+        assert_eq!(original, String::from_utf8(output).unwrap());
+
+        // What the model has no place for keeps the element as it was
+        let original = original.replace("<annotation>", "<annotation id=\"3\">");
+        let cave = tmlu_rs::tmlu::read_cavefile(original.as_bytes());
+        assert!(cave.info.annotations.is_empty());
+        assert_eq!(cave.info.unknown[0].name(), "ListAnnotation");
+        let mut output = Vec::new();
+        tmlu_rs::tmlu::write_cavefile(&mut output, cave.data, cave.info).unwrap();
+        assert_eq!(original, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    pub fn stream_stations() {
+        use tmlu_rs::tmlu::CaveFileReader;
+
+        let mut reader = CaveFileReader::new(open_test_file("test1.tmlu")).unwrap();
+        assert_eq!(reader.info().cave_name, "Fothulen");
+        assert_eq!(reader.info().unit, "m", "unit comes after Data");
+        let ids: Vec<i32> = reader.by_ref().map(|srvd| srvd.unwrap().id).collect();
+        assert_eq!(ids, vec![0]);
+        assert_eq!(reader.info().unit, "ft");
+
+        // Data inside an element after the real one is not taken for the end of the stations
+        let original = std::fs::read_to_string(test_file("test1.tmlu")).unwrap();
+        for trailer in [
+            "<Future><Data/></Future>",
+            "<Future>\n<Data/>\n<unit>m</unit>\n</Future>",
+        ] {
+            let changed =
+                original.replace("<CartoOverlay/>", &format!("<CartoOverlay/>\n{}", trailer));
+            let mut reader =
+                CaveFileReader::new_seekable(std::io::Cursor::new(changed.as_bytes())).unwrap();
+            let ids: Vec<i32> = reader.by_ref().map(|srvd| srvd.unwrap().id).collect();
+            assert_eq!(ids, vec![0]);
+            assert_eq!(reader.info().unit, "ft");
+            let names: Vec<_> = reader.info().unknown.iter().map(|u| u.name()).collect();
+            assert_eq!(names, vec!["Future"]);
+        }
+
+        let reader = CaveFileReader::new_seekable(open_test_file("square_closed.tmlu")).unwrap();
+        assert_eq!(reader.info().cave_name, "a");
+        assert_eq!(reader.info().use_magnetic_azimuth, "false");
+        let info = reader.info().clone();
+        let mut streamed = Vec::new();
+        let stations = reader.map(|srvd| {
+            let mut srvd = srvd.unwrap();
+            srvd.comment = Some(format!("station {}", srvd.id));
+            srvd
+        });
+        tmlu_rs::tmlu::write_cavefile(&mut streamed, stations, info).unwrap();
+
+        let cave = tmlu_rs::tmlu::read_cavefile(streamed.as_slice());
+        assert_eq!(cave.data.len(), 6);
+        assert_eq!(cave.data[5].comment.as_deref(), Some("station 5"));
+        assert_eq!(cave.data[5].closure_to_id, 4);
+    }
+
+    #[test]
+    pub fn stream_reports_invalid_values() {
+        let original = std::fs::read_to_string(test_file("test1.tmlu"))
+            .unwrap()
+            .replace("<ID>0</ID>", "<ID>zero</ID>");
+        let mut reader = tmlu_rs::tmlu::CaveFileReader::new(original.as_bytes()).unwrap();
+        match reader.next() {
+            Some(Err(tmlu_rs::error::Error::InvalidValue { tag, value, .. })) => {
+                assert_eq!(tag, "ID");
+                assert_eq!(value, "zero");
+            }
+            other => panic!("expected invalid value, got {:?}", other),
+        }
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    pub fn borrowed_matches_owned() {
+        use std::borrow::Cow;
+
+        for name in ["test1.tmlu", "circle_closed.tmlu", "bowtie_closed.tmlu"] {
+            let bytes = std::fs::read(test_file(name)).unwrap();
+            let owned = tmlu_rs::tmlu::read_cavefile(bytes.as_slice());
+            let borrowed = tmlu_rs::tmlu::read_cavefile_ref(&bytes).unwrap();
+            assert_eq!(borrowed.info.unit, owned.info.unit);
+            assert_eq!(borrowed.data.len(), owned.data.len());
+            for (b, o) in borrowed.data.into_iter().zip(owned.data) {
+                assert!(matches!(b.azimuth, Cow::Borrowed(_)));
+                assert_eq!(format!("{:?}", b.into_owned()), format!("{:?}", o));
+            }
+        }
+
+        let bytes = std::fs::read(test_file("test1.tmlu")).unwrap();
+        let cave = tmlu_rs::tmlu::read_cavefile_ref(&bytes).unwrap();
+        let explorer = cave.data[0].explorer.as_ref().unwrap();
+        assert!(
+            matches!(explorer, Cow::Owned(_)),
+            "escaped text is unescaped"
+        );
+        assert_eq!(
+            explorer,
+            "<Explorer> Mr, Miyagi</Explorer><Surveyor>Bæ & Bu <oo> </Surveyor>"
+        );
+    }
+
+    #[test]
+    pub fn convert_units_there_and_back() {
+        use tmlu_rs::units::{convert_units, Unit};
+
+        let original = std::fs::read_to_string(test_file("bowtie.tmlu"))
+            .unwrap()
+            .replacen("<lg>0.0</lg>", "<lg>2.5</lg>", 1);
+        let mut cave = tmlu_rs::tmlu::read_cavefile(original.as_bytes());
+        assert_eq!(cave.data[0].shape.radius_collection[0].length, "2.5");
+        let borrowed = tmlu_rs::tmlu::read_cavefile_ref(original.as_bytes()).unwrap();
+        assert_eq!(borrowed.data[0].shape.radius_collection[0].length, "2.5");
+
+        let meters = cave.data.clone();
+        convert_units(&mut cave, Unit::Feet).unwrap();
+        assert_eq!(cave.info.unit, "ft");
+        assert_eq!(cave.data[0].shape.radius_collection[0].length, "8.2021");
+        let leg = cave.data.iter().find(|s| s.length == "32.808399").unwrap();
+        assert_eq!(leg.depth_in, "-1.0", "unset depth in is kept");
+
+        convert_units(&mut cave, Unit::Meters).unwrap();
+        assert_eq!(cave.info.unit, "m");
+        assert_eq!(format!("{:?}", cave.data), format!("{:?}", meters));
+
+        cave.data[1].left = "wide".to_string();
+        assert!(convert_units(&mut cave, Unit::Feet).is_err());
+        assert_eq!(cave.info.unit, "m", "failed conversion changes nothing");
+    }
+
+    #[test]
+    pub fn traverse_closed_square() {
+        let cave = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let traverse = tmlu_rs::traverse::traverse(&cave).unwrap();
+        assert_eq!(traverse.len(), cave.data.len());
+        let corner = traverse.get(2).unwrap();
+        assert!((corner.x - 10.0).abs() < 1e-9 && (corner.y - 10.0).abs() < 1e-9);
+        let closed = traverse.get(4).unwrap() - corner;
+        assert!(closed.length() < 1e-9);
+        assert_eq!(
+            traverse.get(5),
+            Some(corner),
+            "closure sits on its from station"
+        );
+    }
+
+    #[test]
+    pub fn magnetic_to_true_north() {
+        use tmlu_rs::geomag::{convert_to_true_north, traverse_true_north, Model};
+
+        let model = Model::wmm();
+        let mut cave = tmlu_rs::tmlu::read_cavefile(open_test_file("triangle_closed_looperr.tmlu"));
+        assert_eq!(cave.info.use_magnetic_azimuth, "true");
+        let corrected = traverse_true_north(&cave, model).unwrap();
+        let declination = model
+            .declination(60.0, 60.0, 0.0, 2024.0 + 110.0 / 366.0)
+            .unwrap();
+        assert!(declination > 10.0);
+
+        // Later than the embedded model, rather than extrapolated
+        let mut recent = cave.clone();
+        recent.data[2].date = "2026-03-01".to_string();
+        match traverse_true_north(&recent, model) {
+            Err(tmlu_rs::error::Error::OutsideModel { id, date }) => {
+                assert_eq!((id, date.as_str()), (recent.data[2].id, "2026-03-01"))
+            }
+            other => panic!("expected a date outside the model, got {:?}", other.err()),
+        }
+
+        // Older and later legs with the releases of another model added
+        let igrf = std::fs::read_to_string(test_file("dipole_igrf.txt")).unwrap();
+        let mut longer = Model::from_igrf(&igrf).unwrap();
+        longer.extend(model.clone());
+        assert_eq!(
+            (longer.valid_from(), longer.valid_until()),
+            (2015.0, 2030.0)
+        );
+        for (date, year) in [
+            ("2019-04-01", 2019.0 + 90.0 / 365.0),
+            ("2025-04-01", 2025.0 + 90.0 / 365.0),
+        ] {
+            let mut dated = cave.clone();
+            dated.data[1].date = date.to_string();
+            assert!(matches!(
+                traverse_true_north(&dated, model),
+                Err(tmlu_rs::error::Error::OutsideModel { .. })
+            ));
+            convert_to_true_north(&mut dated, &longer).unwrap();
+            let azimuth = dated.data[1].azimuth.parse::<f64>().unwrap();
+            let original = cave.data[1].azimuth.parse::<f64>().unwrap();
+            let expected = longer.declination(60.0, 60.0, 0.0, year).unwrap();
+            assert!(
+                ((azimuth - original - expected + 180.0).rem_euclid(360.0) - 180.0).abs() < 1e-5
+            );
+        }
+
+        convert_to_true_north(&mut cave, model).unwrap();
+        assert_eq!(cave.info.use_magnetic_azimuth, "false");
+        let azimuth = cave.data[2].azimuth.parse::<f64>().unwrap();
+        assert!((azimuth - 45.0 - declination).abs() < 1e-5);
+        assert_eq!(cave.data[0].azimuth, "0.0", "START is left alone");
+
+        let rewritten = tmlu_rs::traverse::traverse(&cave).unwrap();
+        for (id, point) in corrected.iter() {
+            assert!((rewritten.get(id).unwrap() - point).length() < 1e-4);
+        }
+        assert_eq!(
+            traverse_true_north(&cave, model).unwrap().get(3),
+            rewritten.get(3)
+        );
+    }
+
+    #[test]
+    pub fn project_to_utm() {
+        use tmlu_rs::geomag::Model;
+        use tmlu_rs::projection::project_utm;
+
+        let mut cave = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        cave.info.first_start_absolute_elevation = "120.5".to_string();
+        let (utm, stations) = project_utm(&cave, Model::wmm()).unwrap();
+        assert_eq!(utm.utm_zone(), Some(41));
+        let station = |id| *stations.iter().find(|s| s.id == id).unwrap();
+        let (start, north, corner) = (station(0), station(1), station(2));
+        let (_, _, convergence, scale) = utm.project(60.0, 60.0);
+        assert_eq!(start.elevation, 120.5);
+        let bearing = (north.easting - start.easting)
+            .atan2(north.northing - start.northing)
+            .to_degrees();
+        assert!(
+            (bearing + convergence).abs() < 1e-9,
+            "true north turned to grid"
+        );
+        let distance = (north.easting - start.easting).hypot(north.northing - start.northing);
+        assert!((distance - 10.0 * scale).abs() < 1e-9);
+        let closed =
+            (corner.easting - station(4).easting).hypot(corner.northing - station(4).northing);
+        assert!(closed < 1e-9);
+    }
+
+    #[test]
+    pub fn extended_elevation_unfolds_legs() {
+        use std::collections::HashSet;
+        use tmlu_rs::elevation::{extended_elevation, Direction};
+
+        let mut cave = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        cave.data[2].depth = "6.0".to_string();
+        cave.data[2].up = "2.0".to_string();
+        let profile = extended_elevation(&cave, &HashSet::new()).unwrap();
+        let points: Vec<_> = profile
+            .iter()
+            .map(|s| (s.id, s.x.round(), s.z.round()))
+            .collect();
+        assert_eq!(
+            points,
+            vec![
+                (0, 0.0, 0.0),
+                (1, 10.0, 0.0),
+                (3, 10.0, 0.0),
+                (2, 18.0, -6.0),
+                (4, 20.0, 0.0)
+            ]
+        );
+        assert_eq!(profile[3].walls, [(18.0, -4.0), (18.0, -6.0)]);
+
+        let profile = extended_elevation(&cave, &HashSet::from([3])).unwrap();
+        assert_eq!(profile[2].direction, Direction::Left);
+        assert_eq!((profile[4].x, profile[4].from_id), (-20.0, 3));
+
+        cave.data[2].profile_type = "HORIZONTAL".to_string();
+        cave.data[2].left = "1.0".to_string();
+        cave.data[2].right = "3.0".to_string();
+        cave.data[2].shape.has_profile_tilt = "true".to_string();
+        cave.data[2].shape.profile_tilt = "90.0".to_string();
+        let profile = extended_elevation(&cave, &HashSet::new()).unwrap();
+        let walls = profile[3].walls.map(|(x, z)| (x.round(), z.round()));
+        assert_eq!(walls, [(18.0, -5.0), (18.0, -9.0)]);
+    }
+
+    #[test]
+    pub fn survey_stats() {
+        use tmlu_rs::geomag::Model;
+        use tmlu_rs::stats::{stats, Tally};
+
+        let mut cave = tmlu_rs::tmlu::read_cavefile(open_test_file("bowtie_closed.tmlu"));
+        cave.data[4].excluded = "true".to_string();
+        cave.data[2].depth = "12.5".to_string();
+        let stats = stats(&cave, Model::wmm()).unwrap();
+        let tally = |legs, length| Tally { legs, length };
+        assert_eq!(stats.surveyed, tally(5, 170.0));
+        assert_eq!(stats.excluded, tally(1, 50.0));
+        assert_eq!(stats.virtual_legs, Tally::default());
+        assert_eq!((stats.stations, stats.loops), (8, 3));
+        assert_eq!((stats.max_depth, stats.vertical_range), (12.5, 12.5));
+        assert_eq!(stats.by_section[""], tally(3, 110.0));
+        assert_eq!(stats.by_section["b"], tally(2, 60.0));
+        assert_eq!(stats.by_year["2024"], stats.surveyed);
+        assert_eq!(stats.by_explorer["a"], tally(3, 110.0));
+        assert!(stats.east_west > 99.0 && stats.east_west < 101.0);
+        assert!(stats.horizontal_extent >= stats.east_west);
+        assert!(stats.horizontal_extent < stats.east_west + stats.north_south);
+
+        // A station with no position does not stop the rest being measured
+        cave.data[7].azimuth = "NaN".to_string();
+        let stats = tmlu_rs::stats::stats(&cave, Model::wmm()).unwrap();
+        assert!(stats.horizontal_extent > 99.0);
+
+        cave.data[2].explorer = Some("<Explorer>A ,  a</Explorer>".to_string());
+        let stats = tmlu_rs::stats::stats(&cave, Model::wmm()).unwrap();
+        let names: Vec<_> = stats.by_explorer.keys().collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(stats.by_explorer["a"], tally(3, 110.0));
+    }
+
+    #[test]
+    pub fn explorer_totals() {
+        use tmlu_rs::people::{people, same_person};
+
+        let mut cave = tmlu_rs::tmlu::read_cavefile(open_test_file("bowtie_closed.tmlu"));
+        cave.data[3].depth = "20.0".to_string();
+        cave.data[6].explorer =
+            Some("<Explorer>  B , b</Explorer><Surveyor>Sam  Malone</Surveyor>".to_string());
+        cave.data[7].explorer =
+            Some("<Explorer>b</Explorer><Surveyor>sam malone</Surveyor>".to_string());
+        let people = people(&cave).unwrap();
+        let names: Vec<_> = people.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "B", "Sam Malone"]);
+
+        let (a, b, sam) = (&people[0], &people[1], &people[2]);
+        assert_eq!((a.legs_explored, a.legs_surveyed), (4, 4));
+        assert_eq!(a.meters_explored, 160.0);
+        assert_eq!(a.deepest, Some((3, 20.0)));
+        assert_eq!(a.first_date.as_deref(), Some("2024-04-01"));
+        assert_eq!((b.legs_explored, b.meters_explored), (2, 60.0));
+        assert_eq!(b.legs_surveyed, 0);
+        assert_eq!((sam.legs_explored, sam.legs_surveyed), (0, 2));
+        assert_eq!(sam.last_date.as_deref(), Some("2024-04-20"));
+        assert!(same_person(" Sam\tMALONE", "sam malone"));
+    }
+
+    #[test]
+    pub fn closure_loops() {
+        use tmlu_rs::loops::loops;
+        use tmlu_rs::traverse::traverse;
+
+        let cave = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let loops = loops(&cave, &traverse(&cave).unwrap()).unwrap();
+        assert_eq!(loops.len(), 1);
+        assert_eq!((loops[0].from_id, loops[0].to_id), (2, 4));
+        assert_eq!(loops[0].legs, vec![2, 1, 3, 4]);
+        assert_eq!(loops[0].length, 40.0);
+        assert!(loops[0].misclosure.length() < 1e-9);
+
+        let cave = tmlu_rs::tmlu::read_cavefile(open_test_file("triangle_closed_looperr.tmlu"));
+        let loops = tmlu_rs::loops::loops(&cave, &traverse(&cave).unwrap()).unwrap();
+        assert_eq!(loops[0].legs, vec![4, 3, 2]);
+        assert!((loops[0].relative_error() - 0.138).abs() < 1e-3);
+    }
+
+    #[test]
+    pub fn merge_two_files() {
+        use tmlu_rs::geomag::Model;
+        use tmlu_rs::merge::{merge, Conflict};
+
+        let a = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let b = tmlu_rs::tmlu::read_cavefile(open_test_file("test1.tmlu"));
+        let (a_len, b_name) = (a.data.len(), b.info.cave_name.clone());
+        let merged = merge(a, b, Some((3, 0)), Model::wmm()).unwrap();
+        let cave = &merged.cave;
+        assert_eq!(cave.info.unit, "m");
+        assert_eq!(cave.info.use_magnetic_azimuth, "false");
+        assert_eq!(merged.ids[&0], 6);
+        assert_eq!(cave.data[a_len].id, 6);
+        assert_eq!(cave.data[a_len].from_id, -1);
+        let tie = cave.data.last().unwrap();
+        assert_eq!(
+            (tie.station_type.as_str(), tie.from_id, tie.closure_to_id),
+            ("CLOSURE", 3, 6)
+        );
+        assert!(merged.conflicts.contains(&Conflict::UnitConverted {
+            from: "ft".to_string(),
+            to: "m".to_string()
+        }));
+        assert!(merged
+            .conflicts
+            .contains(&Conflict::AzimuthsConverted { file: 1 }));
+        assert!(merged.conflicts.iter().any(|c| matches!(
+            c,
+            Conflict::Header { tag: "caveName", dropped, .. } if *dropped == b_name
+        )));
+        let traverse = tmlu_rs::traverse::traverse(cave).unwrap();
+        assert_eq!(traverse.len(), cave.data.len());
+
+        let a = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let b = tmlu_rs::tmlu::read_cavefile(open_test_file("test1.tmlu"));
+        assert!(merge(a, b, Some((3, 42)), Model::wmm()).is_err());
+    }
+
+    #[test]
+    pub fn merge_three_ways() {
+        use tmlu_rs::geomag::Model;
+        use tmlu_rs::merge::{merge3, Merge3Conflict, Side};
+        use tmlu_rs::units::{convert_units, Unit};
+
+        let base = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let (mut ours, mut theirs) = (base.clone(), base.clone());
+        ours.data[1].length = "11.0".to_string();
+        theirs.data[1].comment = Some("muddy".to_string());
+        theirs.info.cave_name = "Square".to_string();
+        let mut added = base.data[4].clone();
+        added.id = 6;
+        added.name = Some("ours".to_string());
+        ours.data.push(added.clone());
+        added.name = Some("theirs".to_string());
+        theirs.data.push(added.clone());
+        let mut child = added.clone();
+        child.id = 7;
+        child.from_id = 6;
+        theirs.data.push(child);
+
+        let merged = merge3(&base, &ours, &theirs, Model::wmm()).unwrap();
+        assert!(merged.is_clean(), "{:?}", merged.conflicts);
+        let cave = &merged.cave;
+        assert_eq!(cave.info.cave_name, "Square");
+        assert_eq!(cave.data[1].length, "11.0");
+        assert_eq!(cave.data[1].comment.as_deref(), Some("muddy"));
+        assert_eq!(merged.renumbered[&6], 8);
+        let ids: Vec<_> = cave.data[6..].iter().map(|s| (s.id, s.from_id)).collect();
+        assert_eq!(ids, vec![(6, 3), (8, 3), (7, 8)]);
+
+        let merged = merge3(&base, &theirs, &theirs, Model::wmm()).unwrap();
+        assert!(merged.is_clean());
+        assert_eq!(merged.cave.data, theirs.data);
+
+        let (mut ours, mut theirs) = (base.clone(), base.clone());
+        ours.data[2].length = "9.0".to_string();
+        theirs.data[2].length = "9.5".to_string();
+        ours.data[3].azimuth = "1.0".to_string();
+        theirs.data.remove(3);
+        let merged = merge3(&base, &ours, &theirs, Model::wmm()).unwrap();
+        assert_eq!(merged.cave.data[2].length, "9.0");
+        assert_eq!(merged.cave.data.len(), base.data.len());
+        assert_eq!(
+            merged.conflicts,
+            vec![
+                Merge3Conflict::Field {
+                    id: 2,
+                    tag: "LG",
+                    base: "10.0".to_string(),
+                    ours: "9.0".to_string(),
+                    theirs: "9.5".to_string()
+                },
+                Merge3Conflict::DeleteModify {
+                    id: 3,
+                    deleted_by: Side::Theirs
+                },
+            ]
+        );
+
+        let (mut ours, mut theirs) = (base.clone(), base.clone());
+        ours.data[2].comment = Some("ours".to_string());
+        convert_units(&mut theirs, Unit::Feet).unwrap();
+        theirs.data[1].comment = Some("theirs".to_string());
+        ours.data[2].length = "12.25".to_string();
+        let merged = merge3(&base, &ours, &theirs, Model::wmm()).unwrap();
+        assert!(merged.is_clean(), "{:?}", merged.conflicts);
+        assert_eq!(merged.cave.info.unit, "ft");
+        assert_eq!(merged.cave.data[1].length, theirs.data[1].length);
+        assert_eq!(merged.cave.data[1].comment.as_deref(), Some("theirs"));
+        assert_eq!(merged.cave.data[2].comment.as_deref(), Some("ours"));
+        let length: f64 = merged.cave.data[2].length.parse().unwrap();
+        assert!((length - 12.25 / 0.3048).abs() < 1e-5);
+
+        let merged = merge3(&base, &theirs, &ours, Model::wmm()).unwrap();
+        assert!(merged.is_clean(), "{:?}", merged.conflicts);
+        assert_eq!(merged.cave.info.unit, "ft");
+
+        ours.info.use_magnetic_azimuth = "true".to_string();
+        let merged = merge3(&base, &ours, &theirs, Model::wmm()).unwrap();
+        assert!(merged.is_clean(), "{:?}", merged.conflicts);
+        assert_eq!(merged.cave.info.use_magnetic_azimuth, "true");
+        for (a, b) in merged.cave.data.iter().zip(&ours.data) {
+            let (a, b): (f64, f64) = (a.azimuth.parse().unwrap(), b.azimuth.parse().unwrap());
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    pub fn split_by_section_and_subtree() {
+        use tmlu_rs::geomag::Model;
+        use tmlu_rs::split::{split_section, split_subtree};
+        use tmlu_rs::traverse::traverse;
+
+        let cave = tmlu_rs::tmlu::read_cavefile(open_test_file("bowtie_closed.tmlu"));
+        let split = split_section(&cave, "b", Model::wmm()).unwrap();
+        let ids: Vec<_> = split.cave.data.iter().map(|s| (s.id, s.from_id)).collect();
+        assert_eq!(ids, vec![(0, -1), (1, 0), (2, 1)]);
+        assert_eq!(split.ids[&7], 2);
+
+        let mut described = cave.clone();
+        for srvd in described.data.iter_mut() {
+            if srvd.section.as_deref() == Some("b") {
+                srvd.section = Some("b<SectionDescription>d</SectionDescription>".to_string());
+            }
+        }
+        let split = split_section(&described, " b ", Model::wmm()).unwrap();
+        assert_eq!(split.ids.len(), 3);
+        assert!(matches!(
+            split_section(&cave, "z", Model::wmm()),
+            Err(tmlu_rs::error::Error::UnknownSection(_))
+        ));
+
+        let cave = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let whole = split_subtree(&cave, 0, Model::wmm()).unwrap();
+        let closure = whole.cave.data.last().unwrap();
+        assert_eq!((closure.from_id, closure.closure_to_id), (2, 4));
+
+        let split = split_subtree(&cave, 3, Model::wmm()).unwrap();
+        let types: Vec<_> = split
+            .cave
+            .data
+            .iter()
+            .map(|s| s.station_type.as_str())
+            .collect();
+        assert_eq!(
+            types,
+            vec!["START", "REAL", "REAL"],
+            "the closure to 2 is cut"
+        );
+        let before = traverse(&cave).unwrap();
+        let after = traverse(&split.cave).unwrap();
+        for (old, new) in &split.ids {
+            let (a, b) = (before.lat_lon(*old).unwrap(), after.lat_lon(*new).unwrap());
+            assert!((a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9);
+        }
+
+        let split = split_subtree(&cave, 2, Model::wmm()).unwrap();
+        assert_eq!(split.cave.data[0].station_type, "START");
+        assert_eq!(split.cave.data[0].name, cave.data[1].name);
+        let (latitude, _) = before.lat_lon(1).unwrap();
+        assert!(latitude > 60.0);
+        assert_eq!(
+            split.cave.data[0].latitude,
+            tmlu_rs::utils::format_double(latitude),
+            "the cut START is where station 1 was"
+        );
+        assert!(split_subtree(&cave, 99, Model::wmm()).is_err());
+    }
+
+    #[test]
+    pub fn renumber_stations() {
+        use tmlu_rs::renumber::{renumber, Order};
+
+        let original = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let mut cave = original.clone();
+        cave.data.reverse();
+        for srvd in &mut cave.data {
+            srvd.id = srvd.id * 10 + 7;
+            for id in [&mut srvd.from_id, &mut srvd.closure_to_id] {
+                if *id >= 0 {
+                    *id = *id * 10 + 7;
+                }
+            }
+        }
+        let ids = renumber(&mut cave, Order::Sorted);
+        assert_eq!(ids[&27], 2);
+        assert_eq!(format!("{:?}", cave.data), format!("{:?}", original.data));
+
+        let ids = renumber(&mut cave, Order::Traversal);
+        let order: Vec<_> = (0..6).map(|id| ids[&id]).collect();
+        assert_eq!(order, vec![0, 1, 2, 4, 5, 3]);
+        let ids: Vec<_> = cave.data.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5]);
+        let closure = &cave.data[3];
+        assert_eq!(closure.station_type, "CLOSURE");
+        assert_eq!((closure.from_id, closure.closure_to_id), (2, 5));
+    }
+
+    #[test]
+    pub fn diff_cave_files() {
+        use tmlu_rs::diff::{diff, render_text, Change};
+
+        let old = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let mut new = old.clone();
+        assert!(diff(&old, &new).is_empty());
+
+        new.info.cave_name = "Square \"cave\"".to_string();
+        new.data[1].length = "10".to_string();
+        new.data[2].length = "10.5".to_string();
+        new.data[3].comment = Some("wet".to_string());
+        new.data[4].from_id = 1;
+        let mut added = new.data[4].clone();
+        added.id = 6;
+        added.name = Some("A6".to_string());
+        new.data.remove(5);
+        new.data.push(added);
+
+        let changes = diff(&old, &new);
+        assert!(matches!(
+            &changes[0],
+            Change::Header {
+                tag: "caveName",
+                ..
+            }
+        ));
+        assert_eq!(
+            changes[1..],
+            [
+                Change::Measurement {
+                    id: 2,
+                    tag: "LG",
+                    old: "10.0".to_string(),
+                    new: "10.5".to_string()
+                },
+                Change::Attribute {
+                    id: 3,
+                    tag: "CM",
+                    old: None,
+                    new: Some("wet".to_string())
+                },
+                Change::Moved {
+                    id: 4,
+                    old_from: 3,
+                    new_from: 1
+                },
+                Change::Removed {
+                    id: 5,
+                    name: Some("CLOSURE:CLOSURE".to_string())
+                },
+                Change::Added {
+                    id: 6,
+                    name: Some("A6".to_string())
+                },
+            ]
+        );
+
+        let text = render_text(&changes);
+        assert!(text.contains("~ station 2 LG: 10.0 -> 10.5\n"));
+        assert!(text.contains("+ station 6 (A6) added\n"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    pub fn sqlite_there_and_back() {
+        use tmlu_rs::geomag::Model;
+        let original = std::fs::read_to_string(test_file("test1.tmlu"))
+            .unwrap()
+            .replace(
+                "<ListAnnotation/>\n",
+                "<ListAnnotation>\n<annotation>\n<text>Sump</text>\n</annotation>\n</ListAnnotation>\n<future version=\"2\">\n<a>1 &amp; 2</a>\n</future>\n",
+            )
+            .replace("<LK>true</LK>\n", "<LK>true</LK>\n<XYZ>new</XYZ>\n")
+            .replacen("<LG>0.0</LG>", "<LG>10</LG>", 1)
+            .replacen("<opacity>100.0</opacity>", "<opacity>50</opacity>", 1)
+            .replacen("<DP>0.0</DP>", "<DP>1.50</DP>", 1)
+            .replacen(
+                "<RV>",
+                "<RV>\n<ag>NaN</ag>\n<lg>1.0</lg>\n<tc>0.0</tc>\n<tp>0.0</tp>\n</RV>\n<RV>",
+                1,
+            );
+        let mut cave = tmlu_rs::tmlu::read_cavefile(original.as_bytes());
+        assert_eq!(cave.info.annotations.len(), 1);
+        cave.info
+            .layers
+            .push(tmlu_rs::tmlu::LayerList::new("Sketch"));
+        cave.info.carto_overlay.push(tmlu_rs::tmlu::CartoOverlay {
+            file_name: "plan.png".to_string(),
+            x: "12.5".to_string(),
+            y: "-3.25".to_string(),
+            scale: "0.5".to_string(),
+            rotation: "12".to_string(),
+            opacity: "40.0".to_string(),
+            visible: "false".to_string(),
+        });
+        cave.info
+            .carto_linked_surface
+            .push(tmlu_rs::tmlu::CartoLinkedSurface {
+                file_name: "/data/dem.tif".to_string(),
+                name: "DEM".to_string(),
+                ..Default::default()
+            });
+
+        use tmlu_rs::sqlite::rusqlite::Connection;
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        tmlu_rs::sqlite::write_database(&mut conn, &cave, Model::wmm()).unwrap();
+        let depth: f64 = conn
+            .query_row(
+                "SELECT depth FROM survey_data WHERE position = 0",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(depth, 1.5);
+        let raw: i64 = conn
+            .query_row("SELECT count(*) FROM raw_text", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(raw, 5, "10, 1.50, NaN, 50 and 12 need their text");
+        let layers: i64 = conn
+            .query_row("SELECT count(*) FROM layer", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(layers, 3);
+        let text: String = conn
+            .query_row(
+                "SELECT value FROM annotation_field JOIN annotation a ON a.position = annotation \
+                 WHERE a.tag = 'annotation' AND name = 'text'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(text, "Sump");
+        let unit: String = conn
+            .query_row("SELECT unit FROM header", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(unit, cave.info.unit);
+
+        let read = tmlu_rs::sqlite::read_database(&conn).unwrap();
+        assert_eq!(read.info, cave.info);
+        assert_eq!(read.data, cave.data);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    pub fn sqlite_derived_tables() {
+        use tmlu_rs::geomag::Model;
+        use tmlu_rs::sqlite::rusqlite::Connection;
+
+        let cave = tmlu_rs::tmlu::read_cavefile(open_test_file("triangle_closed_looperr.tmlu"));
+        let mut conn = Connection::open_in_memory().unwrap();
+        tmlu_rs::sqlite::write_database(&mut conn, &cave, Model::wmm()).unwrap();
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |r| r.get(0)).unwrap() };
+        assert_eq!(count("SELECT count(*) FROM station_xyz"), 5);
+        assert_eq!(count("SELECT count(*) FROM station_latlon"), 5);
+        assert_eq!(count("SELECT count(*) FROM legs"), 4);
+        let (legs, length, misclosure): (i64, f64, f64) = conn
+            .query_row(
+                "SELECT legs, length, misclosure FROM loops WHERE closure_id = 5",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((legs, length), (3, 30.0));
+        assert!((misclosure - 4.142).abs() < 1e-3);
+        let latitude: f64 = conn
+            .query_row(
+                "SELECT latitude FROM station_latlon WHERE id = 0",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(
+            tmlu_rs::utils::format_double(latitude),
+            cave.data[0].latitude
+        );
+        assert_eq!(
+            tmlu_rs::sqlite::read_database(&conn).unwrap().data,
+            cave.data
+        );
+
+        // No positions is an error, not empty tables
+        let mut recent = cave.clone();
+        recent.data[2].date = "2026-03-01".to_string();
+        let mut conn = Connection::open_in_memory().unwrap();
+        let written = tmlu_rs::sqlite::write_database(&mut conn, &recent, Model::wmm());
+        assert!(matches!(
+            written,
+            Err(tmlu_rs::error::Error::OutsideModel { .. })
+        ));
+        let tables: i64 = conn
+            .query_row("SELECT count(*) FROM sqlite_master", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[test]
+    pub fn blunder_suspects() {
+        use tmlu_rs::blunder::{blunders, Blunder};
+        use tmlu_rs::builder::CaveBuilder;
+        use tmlu_rs::geomag::Model;
+        use tmlu_rs::units::Unit;
+
+        // 12 m north, 12 m east and back, with one leg written down wrong. A triangle, as in a
+        // square the opposite leg could make up for most mistakes
+        let triangle = |id: i32, mistake: fn(&mut tmlu_rs::tmlu::SurveyData)| {
+            let mut builder = CaveBuilder::new("Triangle", Unit::Meters);
+            builder.start("Entrance", 60.0, 10.0, 0.0).unwrap();
+            let mut from = 0;
+            for (length, azimuth) in [(12.0, 0.0), (12.0, 90.0), (16.970563, 225.0)] {
+                from = builder.leg(from, "", length, azimuth, 0.0).unwrap();
+            }
+            builder.closure(from, 0).unwrap();
+            mistake(builder.station_mut(id).unwrap());
+            builder.build().unwrap()
+        };
+
+        let good = blunders(&triangle(2, |_| ()), 0.01, Model::wmm()).unwrap();
+        assert!(good.bad_loops.is_empty());
+        assert!(good.suspects.is_empty());
+
+        let reversed = blunders(
+            &triangle(2, |s| s.azimuth = "270.0".to_string()),
+            0.01,
+            Model::wmm(),
+        )
+        .unwrap();
+        assert_eq!(reversed.bad_loops, vec![4]);
+        let top = &reversed.suspects[0];
+        assert_eq!(
+            (top.id, top.blunder),
+            (2, Blunder::Reversed { azimuth: 90.0 })
+        );
+        assert_eq!(top.loops, vec![4]);
+        assert!(top.improvement(&reversed) > 0.999);
+
+        let digits = blunders(
+            &triangle(2, |s| s.length = "21.0".to_string()),
+            0.01,
+            Model::wmm(),
+        )
+        .unwrap();
+        let top = &digits.suspects[0];
+        assert_eq!(
+            (top.id, top.blunder),
+            (2, Blunder::LengthDigits { length: 12.0 })
+        );
+
+        let feet = blunders(
+            &triangle(2, |s| s.length = "39.370079".to_string()),
+            0.01,
+            Model::wmm(),
+        )
+        .unwrap();
+        let top = &feet.suspects[0];
+        assert_eq!(top.id, 2);
+        match top.blunder {
+            Blunder::FeetAndMeters { length } => assert!((length - 12.0).abs() < 1e-6),
+            other => panic!("expected feet for meters, got {:?}", other),
+        }
+        // Any leg of a single loop closes it when left out, so those come next
+        assert!(feet.suspects[1..4]
+            .iter()
+            .all(|s| s.blunder == Blunder::LeftOut));
+    }
+
+    #[test]
+    pub fn build_a_survey() {
+        use tmlu_rs::builder::CaveBuilder;
+        use tmlu_rs::error::Error;
+        use tmlu_rs::units::Unit;
+        use tmlu_rs::utils::Team;
+
+        let mut builder = CaveBuilder::new("Built", Unit::Meters);
+        builder
+            .date("2024-04-01")
+            .section("Main")
+            .team(&Team::parse("<Explorer>a</Explorer><Surveyor>b</Surveyor>"));
+        let entrance = builder.start("Entrance", 60.0, 10.0, 0.0).unwrap();
+        builder.leg("Entrance", "A1", 10.0, 0.0, 0.0).unwrap();
+        let a2 = builder.leg("A1", "A2", 10.0, 90.0, 5.0).unwrap();
+        builder.leg(a2, "A3", 10.0, 180.0, 5.0).unwrap();
+        let a4 = builder.leg("A3", "", 10.0, 270.0, 0.0).unwrap();
+        let closure = builder.closure(a4, "Entrance").unwrap();
+        assert_eq!((entrance, a2, a4, closure), (0, 2, 4, 5));
+
+        assert!(matches!(
+            builder.leg("Nowhere", "", 1.0, 0.0, 0.0),
+            Err(Error::UnknownStation(_))
+        ));
+        assert!(matches!(
+            builder.leg("A1", "A2", 1.0, 0.0, 0.0),
+            Err(Error::InvalidField { tag, .. }) if tag == "NM"
+        ));
+        assert!(matches!(
+            builder.leg("A1", "", 1.0, 0.0, 5.0),
+            Err(Error::InvalidField { tag, .. }) if tag == "DP"
+        ));
+
+        let mut output = Vec::new();
+        builder.clone().write(&mut output).unwrap();
+        let cave = builder.build().unwrap();
+        assert_eq!(cave.data.len(), 6);
+        let a2 = &cave.data[2];
+        assert_eq!(a2.inclination, "-30.0");
+        assert_eq!(a2.section.as_deref(), Some("Main"));
+        assert_eq!(a2.team().surveyors, vec!["b"]);
+        assert_eq!(cave.data[0].latitude, "60.0");
+
+        let read = tmlu_rs::tmlu::read_cavefile(&output[..]);
+        assert_eq!(read.info.cave_name, "Built");
+        assert_eq!(read.data[..5], cave.data[..5]);
+
+        let loops = tmlu_rs::loops::loops(&cave, &tmlu_rs::traverse::traverse(&cave).unwrap());
+        assert!(loops.unwrap()[0].misclosure.length() < 1e-9);
+
+        let builder = CaveBuilder::new("Empty", Unit::Feet);
+        assert!(matches!(builder.build(), Err(Error::NoStart)));
+    }
+
+    #[test]
+    pub fn csv_shots() {
+        use tmlu_rs::csv::{read_csv, write_csv, CsvFormat, Stations};
+        use tmlu_rs::error::Error;
+        use tmlu_rs::units::Unit;
+
+        let square = tmlu_rs::tmlu::read_cavefile(open_test_file("square_closed.tmlu"));
+        let mut cave = square.clone();
+        cave.data.truncate(1);
+
+        let shots = "From,To,Length (ft),Azimuth,Inclination,Comment\n\
+                     START,A1,10,90,0,\"first, shot\"\n\
+                     A1,A2,10,180,-30,\n\
+                     \n\
+                     A2,START,5,270,0,\n";
+        let format = CsvFormat {
+            unit: Some(Unit::Feet),
+            ..Default::default()
+        };
+        let added = read_csv(shots.as_bytes(), &format, &mut cave).unwrap();
+        assert_eq!(added, vec![1, 2, 3]);
+        assert_eq!(cave.data.len(), 5);
+        let a1 = &cave.data[1];
+        assert_eq!(a1.name.as_deref(), Some("A1"));
+        assert_eq!(a1.comment.as_deref(), Some("first, shot"));
+        assert_eq!((a1.length.as_str(), a1.azimuth.as_str()), ("3.048", "90.0"));
+        assert_eq!(a1.date, "2024-04-01");
+        assert_eq!(cave.data[2].depth, "1.524");
+        let closure = &cave.data[4];
+        assert_eq!(
+            (
+                closure.station_type.as_str(),
+                closure.from_id,
+                closure.closure_to_id
+            ),
+            ("CLOSURE", 3, 0)
+        );
+
+        let bad = "From;To;Length;Azimuth\nSTART;B;1,5;90\nB;C;2;400\n";
+        let before = cave.data.clone();
+        match read_csv(bad.as_bytes(), &CsvFormat::decimal_comma(), &mut cave) {
+            Err(Error::Csv { line, message }) => {
+                assert_eq!(line, 3);
+                assert!(message.contains("Azimuth"), "{}", message);
+            }
+            other => panic!("expected a CSV error, got {:?}", other),
+        }
+        assert_eq!(cave.data, before);
+
+        // A To that is only an id could be a new station or a closure
+        let error = read_csv(
+            "START,2,1,90\n".as_bytes(),
+            &CsvFormat::default(),
+            &mut cave,
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::Csv { line: 1, .. }), "{}", error);
+        assert_eq!(cave.data, before);
+
+        // Out in one locale and back by id
+        let format = CsvFormat {
+            stations: Stations::Ids,
+            ..CsvFormat::decimal_comma()
+        };
+        let mut output = Vec::new();
+        write_csv(&mut output, &square, &format).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("From;To;Length;Azimuth;"), "{}", output);
+        assert!(output.contains("\n0;1;10,0;0,0;"), "{}", output);
+
+        let mut cave = square.clone();
+        cave.data.truncate(1);
+        let added = read_csv(output.as_bytes(), &format, &mut cave).unwrap();
+        assert_eq!(added, vec![1, 2, 3, 4]);
+        for (read, original) in cave.data.iter().zip(&square.data) {
+            assert_eq!(read.id, original.id);
+            assert_eq!(read.from_id, original.from_id);
+            assert_eq!(read.length, original.length);
+            assert_eq!(read.azimuth, original.azimuth);
+        }
+
+        // Id 5 is the CLOSURE row of the square
+        let mut cave = square.clone();
+        let error = read_csv("0;5;3,0;90,0\n".as_bytes(), &format, &mut cave).unwrap_err();
+        assert!(matches!(error, Error::Csv { line: 1, .. }), "{}", error);
+        assert_eq!(cave.data, square.data);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    pub fn command_line() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let tmlu = env!("CARGO_BIN_EXE_tmlu");
+        let run = |args: &[&str], input: &[u8]| {
+            let mut child = Command::new(tmlu)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            (output.status.code(), output.stdout)
+        };
+        let square = test_file("square_closed.tmlu");
+        let square = square.to_str().unwrap();
+        let original = std::fs::read(square).unwrap();
+
+        assert_eq!(run(&["validate", square], b""), (Some(0), vec![]));
+        let (code, json) = run(&["convert", square, "--to", "json"], b"");
+        assert_eq!(code, Some(0));
+        assert_eq!(
+            run(&["convert", "--from", "json"], &json),
+            (Some(0), original)
+        );
+
+        let broken = String::from_utf8(std::fs::read(square).unwrap())
+            .unwrap()
+            .replace("<FRID>2</FRID>", "<FRID>77</FRID>");
+        let (code, problems) = run(&["validate"], broken.as_bytes());
+        assert_eq!(code, Some(1));
+        assert!(String::from_utf8(problems).unwrap().contains("77"));
+
+        let bowtie = test_file("bowtie.tmlu");
+        let (code, changes) = run(&["diff", square, bowtie.to_str().unwrap()], b"");
+        assert_eq!(code, Some(1));
+        assert!(!changes.is_empty());
+        assert_eq!(run(&["info", "no such file.tmlu"], b"").0, Some(2));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn json_there_and_back() {
+        let original = std::fs::read_to_string(test_file("test1.tmlu"))
+            .unwrap()
+            .replace("<LK>true</LK>\n", "<LK>true</LK>\n<XYZ>new</XYZ>\n");
+        let cave = tmlu_rs::tmlu::read_cavefile(original.as_bytes());
+        let json = serde_json::to_string(&cave).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["info"]["caveName"], cave.info.cave_name.as_str());
+        assert_eq!(value["data"][0]["stationType"], "START");
+        assert_eq!(value["data"][0]["shape"]["hasProfileAzimut"], "false");
+        assert_eq!(value["data"][0]["unknown"][0]["after"], "LK");
+
+        let cave: tmlu_rs::tmlu::CaveFile = serde_json::from_str(&json).unwrap();
+        let mut output = Vec::new();
+        tmlu_rs::tmlu::write_cavefile(&mut output, cave.data, cave.info).unwrap();
+        assert_eq!(original, String::from_utf8(output).unwrap());
+
+        let srvd: tmlu_rs::tmlu::SurveyData =
+            serde_json::from_str(r#"{"id": 3, "fromId": 2, "length": "5.0"}"#).unwrap();
+        assert_eq!((srvd.id, srvd.from_id, srvd.length.as_str()), (3, 2, "5.0"));
+        assert_eq!(
+            srvd.station_type,
+            tmlu_rs::tmlu::SurveyData::default().station_type
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn reports_as_json() {
+        use tmlu_rs::geomag::Model;
+
+        let cave = tmlu_rs::tmlu::read_cavefile(open_test_file("bowtie_closed.tmlu"));
+        let stats = tmlu_rs::stats::stats(&cave, Model::wmm()).unwrap();
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["surveyed"]["length"], 220.0);
+        assert_eq!(json["virtual"]["legs"], 0);
+        assert_eq!(json["bySection"]["b"]["legs"], 2);
+        assert_eq!(json["maxDepth"], stats.max_depth);
+        assert_eq!(json["unit"], "m");
+
+        let people = tmlu_rs::people::people(&cave).unwrap();
+        let json = serde_json::to_value(&people).unwrap();
+        assert_eq!(json[0]["name"], "a");
+        assert_eq!(json[0]["legsExplored"], people[0].legs_explored);
+        assert_eq!(json[0]["firstDate"], "2024-04-01");
+
+        let mut new = cave.clone();
+        new.info.cave_name = "Bowtie \"cave\"".to_string();
+        new.data[4].from_id = 1;
+        let json = serde_json::to_value(tmlu_rs::diff::diff(&cave, &new)).unwrap();
+        assert_eq!(json[0]["change"], "header");
+        assert_eq!(json[0]["new"], "Bowtie \"cave\"");
+        assert_eq!(json[1]["change"], "moved");
+        assert_eq!(json[1]["new"], 1);
+    }
+
+    #[test]
+    pub fn write_escapes_text() {
+        let srvd = tmlu_rs::tmlu::SurveyData {
+            name: Some("a<b & c>d".to_string()),
+            color: "0x<ff>".to_string(),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        tmlu_rs::tmlu::write_cavefile(&mut output, vec![srvd], Default::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("<NM>a&lt;b &amp; c&gt;d</NM>"));
+        assert!(output.contains("<CL>0x&lt;ff&gt;</CL>"));
+        assert!(!output.contains("<geoCoding>"));
+
+        let cave = tmlu_rs::tmlu::read_cavefile(output.as_bytes());
+        assert_eq!(cave.data[0].name.as_deref(), Some("a<b & c>d"));
+        assert_eq!(cave.info.geo_coding, None);
+    }
+
+    #[test]
+    #[cfg(feature = "tml")]
+    pub fn tml_there_and_back() {
+        use tmlu_rs::tml::{read_any, read_tml, write_tml, Format};
+
+        let original = std::fs::read(test_file("circle_closed.tmlu")).unwrap();
+        let cave = tmlu_rs::tmlu::read_cavefile(original.as_slice());
+        let mut tml = std::io::Cursor::new(Vec::new());
+        write_tml(&mut tml, cave.data, cave.info).unwrap();
+        let tml = tml.into_inner();
+        assert_eq!(Format::detect(&tml), Format::Tml);
+        assert_eq!(Format::detect(&original), Format::Tmlu);
+        assert!(tml.len() < original.len());
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(&tml)).unwrap();
+        let mut xml = Vec::new();
+        std::io::Read::read_to_end(
+            &mut archive.by_name(tmlu_rs::tml::ENTRY_NAME).unwrap(),
+            &mut xml,
+        )
+        .unwrap();
+        assert_eq!(xml, original, "the container holds the plain tmlu");
+
+        let from_tml = read_tml(std::io::Cursor::new(&tml)).unwrap();
+        let detected = read_any(std::io::Cursor::new(&tml)).unwrap();
+        let plain = read_any(std::io::Cursor::new(&original)).unwrap();
+        assert_eq!(from_tml.info.cave_name, "banana");
+        assert_eq!(detected.data.len(), plain.data.len());
+        assert_eq!(from_tml.data.len(), plain.data.len());
+    }
+
+    #[test]
+    #[cfg(feature = "tml")]
+    pub fn tml_with_other_entry_name() {
+        let mut tml = std::io::Cursor::new(Vec::new());
+        let mut zip = zip::ZipWriter::new(&mut tml);
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("readme.txt", options).unwrap();
+        std::io::Write::write_all(&mut zip, b"not a cave").unwrap();
+        zip.start_file("fothulen.tmlu", options).unwrap();
+        std::io::Write::write_all(&mut zip, &std::fs::read(test_file("test1.tmlu")).unwrap())
+            .unwrap();
+        zip.finish().unwrap();
+
+        tml.set_position(0);
+        let cave = tmlu_rs::tml::read_tml(tml).unwrap();
+        assert_eq!(cave.info.cave_name, "Fothulen");
+    }
+
+    #[test]
     pub fn there_and_back() {
         let files = std::fs::read_dir(testdata())
             .unwrap()